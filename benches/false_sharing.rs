@@ -0,0 +1,39 @@
+//! Threads hammering their own lock out of an array of locks.
+//! Compare `cargo bench --bench false_sharing` with and without `--features cache-padded`.
+
+use std::{hint::black_box, thread, time::Instant};
+
+use rust_concurrency::primitives::{mutex::Mutex, rwlock::RwLock};
+
+const THREADS: usize = 4;
+const ITERATIONS: usize = 1_000_000;
+
+fn main() {
+    println!("cache-padded feature: {}", cfg!(feature = "cache-padded"));
+
+    let mutexes: [Mutex<u64>; THREADS] = std::array::from_fn(|_| Mutex::new(0));
+    let start = Instant::now();
+    thread::scope(|s| {
+        for mutex in &mutexes {
+            s.spawn(move || {
+                for _ in 0..ITERATIONS {
+                    *black_box(mutex).lock() += 1;
+                }
+            });
+        }
+    });
+    println!("Mutex array:  {:?}", start.elapsed());
+
+    let rwlocks: [RwLock<u64>; THREADS] = std::array::from_fn(|_| RwLock::new(0));
+    let start = Instant::now();
+    thread::scope(|s| {
+        for rwlock in &rwlocks {
+            s.spawn(move || {
+                for _ in 0..ITERATIONS {
+                    *black_box(rwlock).write() += 1;
+                }
+            });
+        }
+    });
+    println!("RwLock array: {:?}", start.elapsed());
+}
@@ -0,0 +1,38 @@
+//! Threads taking read locks only, on a `RwLock` against a `ShardedRwLock`.
+//! Run with `cargo bench --bench sharded_rwlock`, the difference needs several cores to show.
+
+use std::{hint::black_box, thread, time::Instant};
+
+use rust_concurrency::primitives::{rwlock::RwLock, sharded_rwlock::ShardedRwLock};
+
+const THREADS: usize = 8;
+const ITERATIONS: usize = 200_000;
+
+fn main() {
+    let rwlock = RwLock::new(1u64);
+    let start = Instant::now();
+    thread::scope(|s| {
+        for _ in 0..THREADS {
+            s.spawn(|| {
+                for _ in 0..ITERATIONS {
+                    black_box(*black_box(&rwlock).read());
+                }
+            });
+        }
+    });
+    println!("RwLock:        {:?}", start.elapsed());
+
+    // Every thread counts its readers on a cache line of its own.
+    let sharded = ShardedRwLock::with_shards(1u64, THREADS);
+    let start = Instant::now();
+    thread::scope(|s| {
+        for _ in 0..THREADS {
+            s.spawn(|| {
+                for _ in 0..ITERATIONS {
+                    black_box(*black_box(&sharded).read());
+                }
+            });
+        }
+    });
+    println!("ShardedRwLock: {:?}", start.elapsed());
+}
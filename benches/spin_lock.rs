@@ -0,0 +1,54 @@
+//! Threads contending on a single `SpinLock`, against a lock spinning on `swap` only.
+//! Run with `cargo bench --bench spin_lock`, the difference needs several cores to show.
+
+use std::{
+    hint::black_box,
+    sync::atomic::{
+        AtomicBool,
+        Ordering::{Acquire, Release},
+    },
+    thread,
+    time::Instant,
+};
+
+use rust_concurrency::primitives::spin_lock::SpinLock;
+
+const THREADS: usize = 4;
+const ITERATIONS: usize = 100_000;
+
+fn main() {
+    let lock = SpinLock::new(0u64);
+    let start = Instant::now();
+    thread::scope(|s| {
+        for _ in 0..THREADS {
+            s.spawn(|| {
+                for _ in 0..ITERATIONS {
+                    *black_box(&lock).lock() += 1;
+                }
+            });
+        }
+    });
+    println!("test and test-and-set: {:?}", start.elapsed());
+    assert_eq!(*lock.lock(), (THREADS * ITERATIONS) as u64);
+
+    // Every waiter writes the cache line on every attempt.
+    let locked = AtomicBool::new(false);
+    let mut counter = 0u64;
+    let counter_ptr = &mut counter as *mut u64 as usize;
+    let start = Instant::now();
+    thread::scope(|s| {
+        for _ in 0..THREADS {
+            s.spawn(|| {
+                for _ in 0..ITERATIONS {
+                    while black_box(&locked).swap(true, Acquire) {
+                        std::hint::spin_loop();
+                    }
+                    unsafe { *(counter_ptr as *mut u64) += 1 };
+                    locked.store(false, Release);
+                }
+            });
+        }
+    });
+    println!("test-and-set:          {:?}", start.elapsed());
+    assert_eq!(counter, (THREADS * ITERATIONS) as u64);
+}
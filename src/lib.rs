@@ -1 +1 @@
-mod primitives;
+pub mod primitives;
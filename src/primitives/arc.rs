@@ -1,52 +1,141 @@
 use std::{
-    cell::UnsafeCell,
-    mem::ManuallyDrop,
+    alloc::{self, Layout},
+    cell::{RefCell, UnsafeCell},
+    cmp::Ordering,
+    collections::HashSet,
+    hash::{Hash, Hasher},
+    mem::{ManuallyDrop, MaybeUninit},
     ops::Deref,
-    ptr::NonNull,
+    panic::{RefUnwindSafe, UnwindSafe},
+    ptr::{self, NonNull},
     sync::atomic::{
         fence, AtomicUsize,
         Ordering::{Acquire, Relaxed, Release},
     },
 };
 
+#[cfg(feature = "arc-arena")]
+use {super::mutex::Mutex, std::marker::PhantomData};
+#[cfg(feature = "arc-finalizers")]
+use {super::spin_lock::SpinLock, std::mem};
+
+#[cfg(feature = "arc-finalizers")]
+type Finalizer<T> = Box<dyn FnOnce(&T) + Send>;
+
 const WEAK_COUNT_LOCKED_VAL: usize = usize::MAX;
 const COUNT_LIMIT: usize = usize::MAX / 2;
 
-pub struct Arc<T> {
+#[cfg(feature = "leak-detection")]
+static OUTSTANDING_ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+/// Same as `std::sync::Arc`, it can be used inside `catch_unwind` only if `T: RefUnwindSafe`:
+/// ```compile_fail
+/// # use rust_concurrency::primitives::arc::Arc;
+/// let arc = Arc::new(std::cell::Cell::new(1));
+/// let _ = std::panic::catch_unwind(move || arc.get());
+/// ```
+/// Also like `std::sync::Arc`, it's only `Send` and `Sync` if `T` is both,
+/// since every clone hands out `&T` and the last one to be dropped drops `T`:
+/// ```
+/// # use rust_concurrency::primitives::arc::Arc;
+/// fn assert_send_sync<T: Send + Sync>() {}
+/// assert_send_sync::<Arc<i32>>();
+/// ```
+/// ```compile_fail
+/// # use rust_concurrency::primitives::arc::Arc;
+/// fn assert_send<T: Send>() {}
+/// assert_send::<Arc<std::cell::Cell<i32>>>();
+/// ```
+/// ```compile_fail
+/// # use rust_concurrency::primitives::arc::Arc;
+/// fn assert_sync<T: Sync>() {}
+/// assert_sync::<Arc<std::cell::Cell<i32>>>();
+/// ```
+/// ```compile_fail
+/// # use rust_concurrency::primitives::arc::Arc;
+/// fn assert_send<T: Send>() {}
+/// assert_send::<Arc<std::sync::MutexGuard<'static, i32>>>();
+/// ```
+pub struct Arc<T: ?Sized> {
     ptr: NonNull<ArcData<T>>,
 }
 
-unsafe impl<T: Sync + Send> Send for Arc<T> {}
-unsafe impl<T: Sync + Send> Sync for Arc<T> {}
+unsafe impl<T: ?Sized + Sync + Send> Send for Arc<T> {}
+unsafe impl<T: ?Sized + Sync + Send> Sync for Arc<T> {}
+
+impl<T: ?Sized> Unpin for Arc<T> {}
+impl<T: ?Sized + RefUnwindSafe> UnwindSafe for Arc<T> {}
+// The `UnsafeCell` is only ever accessed mutably through a unique `Arc`.
+impl<T: ?Sized + RefUnwindSafe> RefUnwindSafe for Arc<T> {}
 
-pub struct Weak<T> {
+pub struct Weak<T: ?Sized> {
     ptr: NonNull<ArcData<T>>,
 }
 
-unsafe impl<T: Sync + Send> Send for Weak<T> {}
-unsafe impl<T: Sync + Send> Sync for Weak<T> {}
+unsafe impl<T: ?Sized + Sync + Send> Send for Weak<T> {}
+unsafe impl<T: ?Sized + Sync + Send> Sync for Weak<T> {}
 
-struct ArcData<T> {
+// `repr(C)` so that `ArcData<MaybeUninit<T>>` and `ArcData<T>` share their layout.
+#[repr(C)]
+struct ArcData<T: ?Sized> {
     /// Number of `Arc`s
     strong: AtomicUsize,
     /// Number of `Weak`s, plus one if there is any `Arc`
     weak: AtomicUsize,
+    /// Where the allocation goes back to once the last `Weak` is gone, if it came from an arena.
+    #[cfg(feature = "arc-arena")]
+    arena: Option<Arc<ArenaSlots>>,
+    /// Run right before the value is dropped, see `Arc::on_last_drop`.
+    /// Registering is rare and quick, a spin lock keeps the header small.
+    #[cfg(feature = "arc-finalizers")]
+    finalizers: SpinLock<Vec<Finalizer<T>>>,
     /// Dropped if there are no `Arc`s pointers left.
     data: UnsafeCell<ManuallyDrop<T>>,
 }
 
 impl<T> Arc<T> {
     pub fn new(data: T) -> Arc<T> {
+        #[cfg(feature = "leak-detection")]
+        OUTSTANDING_ALLOCATIONS.fetch_add(1, Relaxed);
         Arc {
             ptr: NonNull::from(Box::leak(Box::new(ArcData {
                 strong: AtomicUsize::new(1),
                 weak: AtomicUsize::new(1),
+                #[cfg(feature = "arc-arena")]
+                arena: None,
+                #[cfg(feature = "arc-finalizers")]
+                finalizers: SpinLock::new(Vec::new()),
                 data: UnsafeCell::new(ManuallyDrop::new(data)),
             }))),
         }
     }
 
-    pub fn get_mut(&self) -> Option<&mut T> {
+    /// For initializing large values in place, e.g. through `get_mut` before sharing.
+    pub fn new_uninit() -> Arc<MaybeUninit<T>> {
+        Arc::new(MaybeUninit::uninit())
+    }
+
+    /// Gives `f` a `Weak` to the value it's creating, e.g. to store in a `WeakSelf`.
+    /// It can't be upgraded until `new_cyclic` returns.
+    pub fn new_cyclic(f: impl FnOnce(&Weak<T>) -> T) -> Arc<T> {
+        let (weak, init) = Weak::new_uninit();
+        init(f(&weak))
+    }
+}
+
+impl<T: Clone> Arc<T> {
+    /// Like `get_mut`, but clones the value into a new allocation first if it's shared,
+    /// including with `Weak`s, which then no longer see this `Arc`'s value.
+    pub fn make_mut(this: &mut Arc<T>) -> &mut T {
+        if this.get_mut().is_none() {
+            *this = Arc::new(T::clone(this));
+        }
+        this.get_mut().unwrap()
+    }
+}
+
+impl<T: ?Sized> Arc<T> {
+    pub fn get_mut(&mut self) -> Option<&mut T> {
         // Lock weak pointer count if we are the sole weak pointer holder.
         // This prevents any `Arc` from getting downgraded to `Weak`.
         //
@@ -78,13 +167,37 @@ impl<T> Arc<T> {
         unsafe { Some(&mut *self.data().data.get()) }
     }
 
+    /// `get_mut` without any checks, e.g. for initializing a value right after `Arc::new`.
+    ///
+    /// # Safety
+    ///
+    /// No other `Arc` or `Weak` to the same allocation may access the value while the
+    /// returned reference is in use. This includes upgrading a `Weak` and reading through it,
+    /// which would be a data race with the writes made here, and thus undefined behavior.
+    pub unsafe fn get_mut_unchecked(this: &mut Arc<T>) -> &mut T {
+        &mut *this.data().data.get()
+    }
+
     pub fn downgrade(&self) -> Weak<T> {
-        let mut n = self.data().strong.load(Relaxed);
+        self.downgrade_within(None).unwrap()
+    }
+
+    /// Like `downgrade`, but gives up with `None` after spinning `max_spins` times
+    /// while a concurrent `get_mut` has the weak count locked.
+    pub fn try_downgrade(&self, max_spins: u32) -> Option<Weak<T>> {
+        self.downgrade_within(Some(max_spins))
+    }
+
+    fn downgrade_within(&self, mut spins_left: Option<u32>) -> Option<Weak<T>> {
+        let mut n = self.data().weak.load(Relaxed);
         loop {
             // Check whether weak count is locked.
             if n == WEAK_COUNT_LOCKED_VAL {
+                if let Some(spins_left) = &mut spins_left {
+                    *spins_left = spins_left.checked_sub(1)?;
+                }
                 std::hint::spin_loop();
-                n = self.data().strong.load(Relaxed);
+                n = self.data().weak.load(Relaxed);
                 continue;
             }
             assert!(n <= COUNT_LIMIT);
@@ -96,17 +209,383 @@ impl<T> Arc<T> {
                 .compare_exchange_weak(n, n + 1, Acquire, Relaxed)
             {
                 Err(e) => n = e,
-                Ok(_) => return Weak { ptr: self.ptr },
+                Ok(_) => return Some(Weak { ptr: self.ptr }),
             }
         }
     }
 
+    pub fn strong_count(this: &Arc<T>) -> usize {
+        this.data().strong.load(Relaxed)
+    }
+
+    /// Number of `Weak`s, without the implicit one shared by all `Arc`s.
+    pub fn weak_count(this: &Arc<T>) -> usize {
+        // `get_mut` only locks the count while there are no `Weak`s, and can't run concurrently
+        // with `Weak::drop` for the same reason, so the count never ends up at the sentinel otherwise.
+        match this.data().weak.load(Relaxed) {
+            WEAK_COUNT_LOCKED_VAL => 0,
+            n => n - 1,
+        }
+    }
+
+    /// The raw `(strong, weak)` counters, the weak one including the implicit `Weak`
+    /// of all `Arc`s, for checking them from integration tests.
+    #[cfg(feature = "debug-counts")]
+    pub fn debug_counts(this: &Arc<T>) -> (usize, usize) {
+        let data = this.data();
+        (data.strong.load(Relaxed), data.weak.load(Relaxed))
+    }
+
+    /// Registers `f` to run with the value right before it's dropped, on whichever thread
+    /// drops the last `Arc`. For cleanup that needs more than `T::drop` has access to.
+    /// Several of them run in the order they were registered.
+    #[cfg(feature = "arc-finalizers")]
+    pub fn on_last_drop(this: &Arc<T>, f: impl FnOnce(&T) + Send + 'static) {
+        this.data().finalizers.lock().push(Box::new(f));
+    }
+
+    /// Address of the value, stable for as long as the allocation lives.
+    pub fn as_ptr(this: &Arc<T>) -> *const T {
+        unsafe { (*this.ptr.as_ptr()).data.get() as *const T }
+    }
+
+    /// Whether both point to the same allocation, not just equal values.
+    pub fn ptr_eq(this: &Arc<T>, other: &Arc<T>) -> bool {
+        ptr::addr_eq(this.ptr.as_ptr(), other.ptr.as_ptr())
+    }
+
+    /// Shares a part of the value, keeping the whole allocation alive.
+    pub fn project<U: ?Sized>(this: Arc<T>, f: impl FnOnce(&T) -> &U) -> ProjectedArc<T, U> {
+        let ptr = NonNull::from(f(&this));
+        ProjectedArc { parent: this, ptr }
+    }
+
+    /// Lends `f` a `Weak` without touching the weak counter.
+    pub fn with_weak<R>(this: &Arc<T>, f: impl FnOnce(&Weak<T>) -> R) -> R {
+        // Safety: the implicit weak pointer held by all `Arc`s keeps the allocation alive
+        // while `this` is borrowed, and the borrowed `Weak` is never dropped.
+        let weak = ManuallyDrop::new(Weak { ptr: this.ptr });
+        f(&weak)
+    }
+
     fn data(&self) -> &ArcData<T> {
         unsafe { self.ptr.as_ref() }
     }
 }
 
-impl<T> Deref for Arc<T> {
+impl<T: Clone> Arc<[T]> {
+    /// Like `Arc::make_mut`, for slices: clones the whole slice into a new allocation
+    /// if it's shared, e.g. for copy-on-write vectors.
+    /// A separate name, as `Arc::make_mut(&mut arc)` would be ambiguous between the two.
+    pub fn make_mut_slice(this: &mut Arc<[T]>) -> &mut [T] {
+        if this.get_mut().is_none() {
+            *this = Arc::from(&**this);
+        }
+        this.get_mut().unwrap()
+    }
+}
+
+impl<T> Arc<[T]> {
+    /// Moves `len` values starting at `src` into a new allocation.
+    ///
+    /// Safety: the values must be valid, and are owned by the `Arc` afterwards.
+    unsafe fn copy_from_slice(src: *const T, len: usize) -> Arc<[T]> {
+        // Same layout as `repr(C)` gives `ArcData<[T]>`, so `Weak::drop` can free it as a `Box`.
+        let layout = Layout::new::<ArcData<()>>()
+            .extend(Layout::array::<T>(len).unwrap())
+            .unwrap()
+            .0
+            .pad_to_align();
+        // Never zero-sized, the counters are always there.
+        let mem = alloc::alloc(layout);
+        if mem.is_null() {
+            alloc::handle_alloc_error(layout);
+        }
+        let ptr = ptr::slice_from_raw_parts_mut(mem as *mut T, len) as *mut ArcData<[T]>;
+        ptr::write(&mut (*ptr).strong, AtomicUsize::new(1));
+        ptr::write(&mut (*ptr).weak, AtomicUsize::new(1));
+        #[cfg(feature = "arc-arena")]
+        ptr::write(&mut (*ptr).arena, None);
+        #[cfg(feature = "arc-finalizers")]
+        ptr::write(&mut (*ptr).finalizers, SpinLock::new(Vec::new()));
+        ptr::copy_nonoverlapping(src, (*ptr).data.get() as *mut T, len);
+        #[cfg(feature = "leak-detection")]
+        OUTSTANDING_ALLOCATIONS.fetch_add(1, Relaxed);
+        Arc {
+            ptr: NonNull::new_unchecked(ptr),
+        }
+    }
+}
+
+/// Moves the value over into a new allocation, keeping it unsized.
+/// Without unsizing coercions on stable, this is the way to get e.g. an `Arc<dyn Fn()>`.
+impl<T: ?Sized> From<Box<T>> for Arc<T> {
+    fn from(value: Box<T>) -> Self {
+        let value_layout = Layout::for_value(&*value);
+        let layout = Layout::new::<ArcData<()>>()
+            .extend(value_layout)
+            .unwrap()
+            .0
+            .pad_to_align();
+        unsafe {
+            let mem = alloc::alloc(layout);
+            if mem.is_null() {
+                alloc::handle_alloc_error(layout);
+            }
+            let value = Box::into_raw(value);
+            // Same metadata (length, vtable) as the box, but the address and provenance of
+            // `mem`: only the data half of the fat pointer is overwritten.
+            let mut ptr = value as *mut ArcData<T>;
+            ptr::write(&mut ptr as *mut *mut ArcData<T> as *mut *mut u8, mem);
+            ptr::write(ptr::addr_of_mut!((*ptr).strong), AtomicUsize::new(1));
+            ptr::write(ptr::addr_of_mut!((*ptr).weak), AtomicUsize::new(1));
+            #[cfg(feature = "arc-arena")]
+            ptr::write(ptr::addr_of_mut!((*ptr).arena), None);
+            #[cfg(feature = "arc-finalizers")]
+            ptr::write(
+                ptr::addr_of_mut!((*ptr).finalizers),
+                SpinLock::new(Vec::new()),
+            );
+            ptr::copy_nonoverlapping(
+                value as *const u8,
+                ptr::addr_of_mut!((*ptr).data) as *mut u8,
+                value_layout.size(),
+            );
+            // The value moved, only free the box.
+            if value_layout.size() != 0 {
+                alloc::dealloc(value as *mut u8, value_layout);
+            }
+            #[cfg(feature = "leak-detection")]
+            OUTSTANDING_ALLOCATIONS.fetch_add(1, Relaxed);
+            Arc {
+                ptr: NonNull::new_unchecked(ptr),
+            }
+        }
+    }
+}
+
+impl<T: Clone> From<&[T]> for Arc<[T]> {
+    fn from(slice: &[T]) -> Self {
+        slice.to_vec().into()
+    }
+}
+
+impl<T> From<Vec<T>> for Arc<[T]> {
+    fn from(mut vec: Vec<T>) -> Self {
+        unsafe {
+            let arc = Arc::copy_from_slice(vec.as_ptr(), vec.len());
+            // The values belong to the `Arc` now, only free the buffer.
+            vec.set_len(0);
+            arc
+        }
+    }
+}
+
+impl From<&str> for Arc<str> {
+    fn from(s: &str) -> Self {
+        let bytes = ManuallyDrop::new(unsafe { Arc::copy_from_slice(s.as_ptr(), s.len()) });
+        // Safety: `str` has the layout of `[u8]`, and the bytes came from a `str`.
+        // The counts move over as they are.
+        Arc {
+            ptr: unsafe { NonNull::new_unchecked(bytes.ptr.as_ptr() as *mut ArcData<str>) },
+        }
+    }
+}
+
+impl From<String> for Arc<str> {
+    fn from(s: String) -> Self {
+        Arc::from(s.as_str())
+    }
+}
+
+#[cfg(feature = "leak-detection")]
+impl Arc<()> {
+    /// Number of `Arc` allocations not freed yet, across all types.
+    /// Meant for asserting that tests don't leak.
+    pub fn outstanding_allocations() -> usize {
+        OUTSTANDING_ALLOCATIONS.load(Relaxed)
+    }
+}
+
+/// Hands out `Arc`s whose allocations are reused once they're freed, instead of going
+/// back to the global allocator. Either of the arena and its `Arc`s can outlive the other,
+/// the slots are only freed once both are gone.
+#[cfg(feature = "arc-arena")]
+pub struct ArcArena<T> {
+    slots: Arc<ArenaSlots>,
+    _marker: PhantomData<fn(T) -> T>,
+}
+
+/// Freed `ArcData<T>` allocations, type-erased so `ArcData` doesn't have to name `T` twice.
+#[cfg(feature = "arc-arena")]
+struct ArenaSlots {
+    free: Mutex<Vec<NonNull<u8>>>,
+    layout: Layout,
+    allocated: AtomicUsize,
+}
+
+// The slots are plain memory, owned by whoever popped them off the free list.
+#[cfg(feature = "arc-arena")]
+unsafe impl Send for ArenaSlots {}
+#[cfg(feature = "arc-arena")]
+unsafe impl Sync for ArenaSlots {}
+
+#[cfg(feature = "arc-arena")]
+impl<T> ArcArena<T> {
+    pub fn new() -> Self {
+        Self {
+            slots: Arc::new(ArenaSlots {
+                free: Mutex::new(Vec::new()),
+                layout: Layout::new::<ArcData<T>>(),
+                allocated: AtomicUsize::new(0),
+            }),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Like `Arc::new`, but takes a free slot if there is one.
+    pub fn alloc(&self, data: T) -> Arc<T> {
+        let slot = self.slots.free.lock().pop();
+        let ptr = match slot {
+            Some(slot) => slot.cast::<ArcData<T>>(),
+            None => {
+                self.slots.allocated.fetch_add(1, Relaxed);
+                let layout = self.slots.layout;
+                let mem = unsafe { alloc::alloc(layout) };
+                NonNull::new(mem)
+                    .unwrap_or_else(|| alloc::handle_alloc_error(layout))
+                    .cast()
+            }
+        };
+        // Safety: the slot is unused, and laid out for `ArcData<T>`.
+        unsafe {
+            ptr.as_ptr().write(ArcData {
+                strong: AtomicUsize::new(1),
+                weak: AtomicUsize::new(1),
+                arena: Some(self.slots.clone()),
+                #[cfg(feature = "arc-finalizers")]
+                finalizers: SpinLock::new(Vec::new()),
+                data: UnsafeCell::new(ManuallyDrop::new(data)),
+            });
+        }
+        #[cfg(feature = "leak-detection")]
+        OUTSTANDING_ALLOCATIONS.fetch_add(1, Relaxed);
+        Arc { ptr }
+    }
+
+    /// Number of slots taken from the global allocator so far.
+    pub fn allocated(&self) -> usize {
+        self.slots.allocated.load(Relaxed)
+    }
+}
+
+#[cfg(feature = "arc-arena")]
+impl<T> Default for ArcArena<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(feature = "arc-arena")]
+impl Drop for ArenaSlots {
+    fn drop(&mut self) {
+        // Only free slots are left once every `Arc` and `Weak` is gone.
+        // Pushing a slot can't leave the list half updated, so poisoning doesn't matter.
+        let free = self.free.get_mut().unwrap_or_else(|err| err.into_inner());
+        for slot in free.drain(..) {
+            unsafe { alloc::dealloc(slot.as_ptr(), self.layout) };
+        }
+    }
+}
+
+/// Lets code be generic over `std::sync::Arc` and this crate's `Arc`,
+/// which otherwise only differ in their path.
+#[cfg(feature = "shared-ptr")]
+pub trait SharedPtr<T: ?Sized>: Deref<Target = T> + Clone {
+    fn new(value: T) -> Self
+    where
+        T: Sized;
+
+    fn ptr_eq(this: &Self, other: &Self) -> bool;
+}
+
+#[cfg(feature = "shared-ptr")]
+impl<T: ?Sized> SharedPtr<T> for Arc<T> {
+    fn new(value: T) -> Self
+    where
+        T: Sized,
+    {
+        Arc::new(value)
+    }
+
+    fn ptr_eq(this: &Self, other: &Self) -> bool {
+        Arc::ptr_eq(this, other)
+    }
+}
+
+#[cfg(feature = "shared-ptr")]
+impl<T: ?Sized> SharedPtr<T> for std::sync::Arc<T> {
+    fn new(value: T) -> Self
+    where
+        T: Sized,
+    {
+        std::sync::Arc::new(value)
+    }
+
+    fn ptr_eq(this: &Self, other: &Self) -> bool {
+        std::sync::Arc::ptr_eq(this, other)
+    }
+}
+
+impl<T: ?Sized> Arc<RefCell<Option<Arc<T>>>> {
+    /// Drops the link, for breaking up a cycle of `Arc`s that would otherwise leak.
+    /// The old value is dropped after the `RefCell` borrow ends, so its drop may use the link.
+    pub fn clear_link(&self) {
+        drop(self.take());
+    }
+}
+
+impl<T> Arc<MaybeUninit<T>> {
+    /// # Safety
+    ///
+    /// The value must be fully initialized.
+    pub unsafe fn assume_init(this: Arc<MaybeUninit<T>>) -> Arc<T> {
+        // The counts move over to the returned `Arc` as they are.
+        let this = ManuallyDrop::new(this);
+        Arc {
+            ptr: this.ptr.cast(),
+        }
+    }
+}
+
+/// `Arc`s of the same allocation are equal without comparing the values.
+/// That's only correct if every value equals itself, so unlike `std::sync::Arc`,
+/// there's no `PartialEq` for `T` that's only `PartialEq` (like `f64`), compare `*a == *b` instead.
+impl<T: ?Sized + Eq> PartialEq for Arc<T> {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(self, other) || **self == **other
+    }
+}
+
+impl<T: ?Sized + Eq> Eq for Arc<T> {}
+
+impl<T: ?Sized + Ord> PartialOrd for Arc<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Same as `PartialEq`, the same allocation is `Equal` without comparing the values.
+impl<T: ?Sized + Ord> Ord for Arc<T> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        if Arc::ptr_eq(self, other) {
+            return Ordering::Equal;
+        }
+        (**self).cmp(&**other)
+    }
+}
+
+impl<T: ?Sized> Deref for Arc<T> {
     type Target = T;
 
     fn deref(&self) -> &Self::Target {
@@ -115,43 +594,163 @@ impl<T> Deref for Arc<T> {
     }
 }
 
-impl<T> Clone for Arc<T> {
+impl<T: ?Sized> Clone for Arc<T> {
     fn clone(&self) -> Self {
         if (self.data().strong.fetch_add(1, Relaxed)) >= COUNT_LIMIT {
             std::process::abort();
         }
         Arc { ptr: self.ptr }
     }
+
+    fn clone_from(&mut self, source: &Self) {
+        // Same allocation, the counts are already right.
+        if Arc::ptr_eq(self, source) {
+            return;
+        }
+        *self = source.clone();
+    }
 }
 
-impl<T> Drop for Arc<T> {
+impl<T: ?Sized> Drop for Arc<T> {
     fn drop(&mut self) {
         if self.data().strong.fetch_sub(1, Release) == 1 {
             fence(Acquire);
-            // Safety: Strong counter is zero, nothing can access the data anymore.
-            unsafe {
-                ManuallyDrop::drop(&mut *self.data().data.get());
+            // Runs at the end, even if a finalizer panics.
+            let _last = LastArc { ptr: self.ptr };
+            #[cfg(feature = "arc-finalizers")]
+            {
+                // Taken out first, so the lock isn't held while they run.
+                let finalizers = mem::take(&mut *self.data().finalizers.lock());
+                for finalizer in finalizers {
+                    finalizer(unsafe { &*self.data().data.get() });
+                }
             }
-            // No `Arc`s left, drop the implicit weak pointer that represents all `Arc`s.
-            drop(Weak { ptr: self.ptr });
+        }
+    }
+}
+
+/// Drops the value once the last `Arc` is gone.
+struct LastArc<T: ?Sized> {
+    ptr: NonNull<ArcData<T>>,
+}
+
+impl<T: ?Sized> Drop for LastArc<T> {
+    fn drop(&mut self) {
+        // Safety: Strong counter is zero, nothing can access the data anymore.
+        unsafe {
+            ManuallyDrop::drop(&mut *(*self.ptr.as_ptr()).data.get());
+        }
+        // No `Arc`s left, drop the implicit weak pointer that represents all `Arc`s.
+        drop(Weak { ptr: self.ptr });
+    }
+}
+
+/// Returned by `Arc::project`, derefs to a part of the parent's value.
+pub struct ProjectedArc<T: ?Sized, U: ?Sized> {
+    parent: Arc<T>,
+    /// Points into the parent's value, which lives as long as `parent` does.
+    ptr: NonNull<U>,
+}
+
+unsafe impl<T: ?Sized + Sync + Send, U: ?Sized + Sync> Send for ProjectedArc<T, U> {}
+unsafe impl<T: ?Sized + Sync + Send, U: ?Sized + Sync> Sync for ProjectedArc<T, U> {}
+
+impl<T: ?Sized, U: ?Sized> ProjectedArc<T, U> {
+    pub fn parent(this: &ProjectedArc<T, U>) -> &Arc<T> {
+        &this.parent
+    }
+}
+
+impl<T: ?Sized, U: ?Sized> Deref for ProjectedArc<T, U> {
+    type Target = U;
+
+    fn deref(&self) -> &U {
+        unsafe { self.ptr.as_ref() }
+    }
+}
+
+impl<T: ?Sized, U: ?Sized> Clone for ProjectedArc<T, U> {
+    fn clone(&self) -> Self {
+        ProjectedArc {
+            parent: self.parent.clone(),
+            ptr: self.ptr,
         }
     }
 }
 
 impl<T> Weak<T> {
+    /// A `Weak` to a value that doesn't exist yet, along with the initializer that creates it
+    /// and returns the first `Arc`. Until then, the `Weak` can't be upgraded.
+    /// If the initializer is dropped without being called, the allocation is freed
+    /// once the `Weak`s are gone.
+    pub fn new_uninit() -> (Weak<T>, impl FnOnce(T) -> Arc<T>) {
+        #[cfg(feature = "leak-detection")]
+        OUTSTANDING_ALLOCATIONS.fetch_add(1, Relaxed);
+        let data: Box<ArcData<MaybeUninit<T>>> = Box::new(ArcData {
+            strong: AtomicUsize::new(0),
+            // The returned `Weak` and the initializer's.
+            weak: AtomicUsize::new(2),
+            #[cfg(feature = "arc-arena")]
+            arena: None,
+            #[cfg(feature = "arc-finalizers")]
+            finalizers: SpinLock::new(Vec::new()),
+            data: UnsafeCell::new(ManuallyDrop::new(MaybeUninit::uninit())),
+        });
+        // `repr(C)`, so the layout is the same, and `ManuallyDrop` keeps the value from being
+        // dropped if it never gets initialized.
+        let ptr = NonNull::from(Box::leak(data)).cast::<ArcData<T>>();
+        let initializer = Weak { ptr };
+        (Weak { ptr }, move |value| {
+            // The initializer's `Weak` becomes the one shared by all `Arc`s.
+            let weak = ManuallyDrop::new(initializer);
+            unsafe { ptr::write(weak.data().data.get(), ManuallyDrop::new(value)) };
+            // Release publishes the value to `upgrade`.
+            weak.data().strong.store(1, Release);
+            Arc { ptr: weak.ptr }
+        })
+    }
+}
+
+impl<T: ?Sized> Weak<T> {
+    /// Address of the value, even if it was dropped already.
+    /// The `Weak` keeps the allocation alive, so it can't be reused meanwhile.
+    pub fn as_ptr(&self) -> *const T {
+        unsafe { (*self.ptr.as_ptr()).data.get() as *const T }
+    }
+
+    pub fn ptr_eq(&self, other: &Weak<T>) -> bool {
+        ptr::addr_eq(self.ptr.as_ptr(), other.ptr.as_ptr())
+    }
+
+    /// See `Arc::debug_counts`.
+    #[cfg(feature = "debug-counts")]
+    pub fn debug_counts(&self) -> (usize, usize) {
+        let data = self.data();
+        (data.strong.load(Relaxed), data.weak.load(Relaxed))
+    }
+
+    /// Whether the value is gone, so `upgrade` would fail. Cheaper than trying an upgrade,
+    /// e.g. for pruning dead cache entries. Once true, it stays true, except for a `Weak` from
+    /// `Weak::new_uninit`: that one also counts as expired until the initializer runs.
+    pub fn is_expired(&self) -> bool {
+        self.data().strong.load(Relaxed) == 0
+    }
+
+    /// Also `None` if there are already `COUNT_LIMIT` `Arc`s, instead of overflowing the count.
     pub fn upgrade(&self) -> Option<Arc<T>> {
         let mut n = self.data().strong.load(Relaxed);
 
         loop {
-            if n == 0 {
+            // The CAS only succeeds if the count is still `n`, so it never goes past the limit.
+            if n == 0 || n >= COUNT_LIMIT {
                 return None;
             }
-            assert!(n <= COUNT_LIMIT);
 
+            // Acquire to see the value if it was only just initialized, see `new_uninit`.
             match self
                 .data()
                 .strong
-                .compare_exchange_weak(n, n + 1, Relaxed, Relaxed)
+                .compare_exchange_weak(n, n + 1, Acquire, Relaxed)
             {
                 Err(e) => n = e,
                 Ok(_) => return Some(Arc { ptr: self.ptr }),
@@ -159,25 +758,190 @@ impl<T> Weak<T> {
         }
     }
 
+    /// Like `clone`, but `None` instead of aborting once there are `COUNT_LIMIT` `Weak`s.
+    pub fn try_clone(&self) -> Option<Weak<T>> {
+        let mut n = self.data().weak.load(Relaxed);
+        loop {
+            // Only the uncounted `Weak` lent by `Arc::with_weak` can see the count locked,
+            // that's like `downgrade`, so it waits for the concurrent `get_mut` the same way.
+            if n == WEAK_COUNT_LOCKED_VAL {
+                std::hint::spin_loop();
+                n = self.data().weak.load(Relaxed);
+                continue;
+            }
+            if n >= COUNT_LIMIT {
+                return None;
+            }
+            // Acquire synchronises with `Arc::get_mut` release store, as in `downgrade`.
+            match self
+                .data()
+                .weak
+                .compare_exchange_weak(n, n + 1, Acquire, Relaxed)
+            {
+                Err(e) => n = e,
+                Ok(_) => return Some(Weak { ptr: self.ptr }),
+            }
+        }
+    }
+
     fn data(&self) -> &ArcData<T> {
         unsafe { self.ptr.as_ref() }
     }
 }
 
-impl<T> Clone for Weak<T> {
+/// Field for values that hand out `Arc`s to themselves, set up with `Arc::new_cyclic`.
+pub struct WeakSelf<T> {
+    weak: Option<Weak<T>>,
+}
+
+impl<T> WeakSelf<T> {
+    pub const fn new() -> Self {
+        Self { weak: None }
+    }
+
+    /// Meant for construction, before the value is shared.
+    pub fn set(&mut self, weak: &Weak<T>) {
+        self.weak = Some(weak.clone());
+    }
+
+    /// A new `Arc` to the value holding this field.
+    ///
+    /// # Panics
+    ///
+    /// If `set` wasn't called, or while the value is still being created or already dropped.
+    pub fn arc(&self) -> Arc<T> {
+        self.weak
+            .as_ref()
+            .and_then(Weak::upgrade)
+            .expect("WeakSelf isn't set or its value isn't alive")
+    }
+}
+
+impl<T> Default for WeakSelf<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// `Weak`s to observers or similar, which don't keep them alive.
+pub struct WeakList<T: ?Sized> {
+    weaks: Vec<Weak<T>>,
+}
+
+impl<T: ?Sized> WeakList<T> {
+    pub const fn new() -> Self {
+        Self { weaks: Vec::new() }
+    }
+
+    pub fn push(&mut self, arc: &Arc<T>) {
+        self.weaks.push(arc.downgrade());
+    }
+
+    /// Including entries whose value is gone already, until they're pruned.
+    pub fn len(&self) -> usize {
+        self.weaks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.weaks.is_empty()
+    }
+
+    /// The values still alive, in the order they were pushed.
+    pub fn upgrade_all(&self) -> Vec<Arc<T>> {
+        self.weaks.iter().filter_map(Weak::upgrade).collect()
+    }
+
+    /// Like `upgrade_all`, and drops the dead entries in the same pass.
+    pub fn upgrade_and_prune(&mut self) -> Vec<Arc<T>> {
+        let mut live = Vec::with_capacity(self.weaks.len());
+        self.weaks.retain(|weak| match weak.upgrade() {
+            Some(arc) => {
+                live.push(arc);
+                true
+            }
+            None => false,
+        });
+        live
+    }
+}
+
+impl<T: ?Sized> Default for WeakList<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Compares and hashes an `Arc` or `Weak` by the allocation it points to, not by value,
+/// e.g. for sets of observers.
+pub struct ByAddress<P>(pub P);
+
+impl<T: ?Sized> ByAddress<Arc<T>> {
+    fn addr(&self) -> *const () {
+        Arc::as_ptr(&self.0) as *const ()
+    }
+}
+
+impl<T: ?Sized> ByAddress<Weak<T>> {
+    fn addr(&self) -> *const () {
+        self.0.as_ptr() as *const ()
+    }
+}
+
+impl<T: ?Sized> PartialEq for ByAddress<Arc<T>> {
+    fn eq(&self, other: &Self) -> bool {
+        self.addr() == other.addr()
+    }
+}
+
+impl<T: ?Sized> Eq for ByAddress<Arc<T>> {}
+
+impl<T: ?Sized> Hash for ByAddress<Arc<T>> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.addr().hash(state);
+    }
+}
+
+impl<T: ?Sized> PartialEq for ByAddress<Weak<T>> {
+    fn eq(&self, other: &Self) -> bool {
+        self.addr() == other.addr()
+    }
+}
+
+impl<T: ?Sized> Eq for ByAddress<Weak<T>> {}
+
+impl<T: ?Sized> Hash for ByAddress<Weak<T>> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.addr().hash(state);
+    }
+}
+
+/// Removes `Arc`s pointing to an allocation that an earlier one already points to,
+/// keeping the order otherwise. Equal values in different allocations all stay.
+pub fn dedup_by_ptr<T: ?Sized>(arcs: &mut Vec<Arc<T>>) {
+    let mut seen = HashSet::with_capacity(arcs.len());
+    arcs.retain(|arc| seen.insert(Arc::as_ptr(arc) as *const ()));
+}
+
+impl<T: ?Sized> Clone for Weak<T> {
     fn clone(&self) -> Self {
-        if (self.data().weak.fetch_add(1, Relaxed)) >= COUNT_LIMIT {
-            std::process::abort();
-        }
-        Weak { ptr: self.ptr }
+        // Not a plain `fetch_add`, the count may be locked by `get_mut`, see `try_clone`.
+        self.try_clone().unwrap_or_else(|| std::process::abort())
     }
 }
 
-impl<T> Drop for Weak<T> {
+impl<T: ?Sized> Drop for Weak<T> {
     fn drop(&mut self) {
         // Release synchronises with `Arc::get_mut` acquire load.
         if self.data().weak.fetch_sub(1, Release) == 1 {
             fence(Acquire);
+            #[cfg(feature = "leak-detection")]
+            OUTSTANDING_ALLOCATIONS.fetch_sub(1, Relaxed);
+            // Arena slots are recycled, the value itself is already dropped.
+            #[cfg(feature = "arc-arena")]
+            if let Some(arena) = unsafe { (*self.ptr.as_ptr()).arena.take() } {
+                arena.free.lock().push(self.ptr.cast());
+                return;
+            }
             // Safety: Weak counter is zero, nothing can access the pointer anymore.
             unsafe {
                 drop(Box::from_raw(self.ptr.as_ptr()));
@@ -189,7 +953,13 @@ impl<T> Drop for Weak<T> {
 #[cfg(test)]
 mod test {
     use super::*;
-    use std::{cell::RefCell, thread::spawn};
+    use crate::primitives::mutex::Mutex;
+    use std::{
+        panic::catch_unwind,
+        sync::atomic::AtomicBool,
+        thread::{self, spawn},
+        time::Duration,
+    };
 
     static DETECT_DROP_COUNT: AtomicUsize = AtomicUsize::new(0);
 
@@ -203,11 +973,16 @@ mod test {
         }
     }
 
-    fn check_counters(
-        ptr: NonNull<ArcData<(&str, DetectDrop)>>,
-        exp_strong: usize,
-        exp_weak: usize,
-    ) {
+    /// Like `DetectDrop`, for tests that need a counter of their own.
+    struct CountDrop(&'static AtomicUsize);
+
+    impl Drop for CountDrop {
+        fn drop(&mut self) {
+            self.0.fetch_add(1, Relaxed);
+        }
+    }
+
+    fn check_counters<T: ?Sized>(ptr: NonNull<ArcData<T>>, exp_strong: usize, exp_weak: usize) {
         assert_eq!(unsafe { ptr.as_ref().strong.load(Relaxed) }, exp_strong);
         assert_eq!(unsafe { ptr.as_ref().weak.load(Relaxed) }, exp_weak);
     }
@@ -216,7 +991,7 @@ mod test {
     fn test_various() {
         DETECT_DROP_COUNT.store(0, Relaxed);
 
-        let strong = Arc::new(("hello", DetectDrop));
+        let mut strong = Arc::new(("hello", DetectDrop));
         assert!(strong.get_mut().is_some());
 
         let weak1 = strong.downgrade();
@@ -260,6 +1035,661 @@ mod test {
         assert!(weak3.upgrade().is_none());
     }
 
+    #[test]
+    fn test_get_mut_race() {
+        let mut arc = Arc::new(AtomicUsize::new(0));
+        let slot: Mutex<Option<Weak<AtomicUsize>>> = Mutex::new(None);
+        let done = AtomicBool::new(false);
+        thread::scope(|s| {
+            for _ in 0..3 {
+                s.spawn(|| {
+                    while !done.load(Relaxed) {
+                        let weak = slot.lock().take();
+                        if let Some(strong) = weak.as_ref().and_then(Weak::upgrade) {
+                            strong.fetch_add(1, Relaxed);
+                            drop(weak);
+                            // Hand out more weak pointers while `get_mut` might be running.
+                            let weak = strong.downgrade();
+                            strong.fetch_add(1, Relaxed);
+                            drop(strong);
+                            *slot.lock() = Some(weak);
+                        }
+                    }
+                });
+            }
+            for _ in 0..20_000 {
+                if slot.lock().is_none() {
+                    *slot.lock() = Some(arc.downgrade());
+                }
+                if let Some(value) = arc.get_mut() {
+                    // Nobody else may reach the value now, so nobody can bump it.
+                    value.store(0, Relaxed);
+                    for _ in 0..10 {
+                        std::hint::spin_loop();
+                    }
+                    assert_eq!(value.load(Relaxed), 0);
+                }
+            }
+            done.store(true, Relaxed);
+        });
+        drop(slot);
+        check_counters(arc.ptr, 1, 1);
+        assert!(arc.get_mut().is_some());
+    }
+
+    #[test]
+    fn test_slice() {
+        static DROPS: AtomicUsize = AtomicUsize::new(0);
+        let arc: Arc<[CountDrop]> =
+            vec![CountDrop(&DROPS), CountDrop(&DROPS), CountDrop(&DROPS)].into();
+        assert_eq!(arc.len(), 3);
+        let weak = arc.downgrade();
+        drop(arc);
+        assert_eq!(DROPS.load(Relaxed), 3);
+        assert!(weak.upgrade().is_none());
+
+        let mut arc = Arc::<[u64]>::from(&[1, 2, 3][..]);
+        arc.get_mut().unwrap()[0] = 4;
+        assert_eq!(*arc.clone(), [4, 2, 3]);
+        assert_eq!(Arc::<[()]>::from(vec![(); 2]).len(), 2);
+    }
+
+    #[test]
+    fn test_dyn_fn() {
+        static DROPS: AtomicUsize = AtomicUsize::new(0);
+        let offset = CountDrop(&DROPS);
+        let f: Arc<dyn Fn(i32) -> i32 + Send + Sync> = Arc::from(Box::new(move |x| {
+            let _ = &offset;
+            x + 1
+        })
+            as Box<dyn Fn(i32) -> i32 + Send + Sync>);
+
+        let handles: Vec<_> = (0..4)
+            .map(|i| {
+                let f = f.clone();
+                spawn(move || f(i))
+            })
+            .collect();
+        let results: Vec<_> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+        assert_eq!(results, [1, 2, 3, 4]);
+        assert_eq!(f(10), 11);
+
+        drop(f);
+        assert_eq!(DROPS.load(Relaxed), 1);
+        let empty: Arc<dyn Fn() -> i32> = Arc::from(Box::new(|| 7) as Box<dyn Fn() -> i32>);
+        assert_eq!(empty(), 7);
+    }
+
+    #[test]
+    fn test_from_box() {
+        // Single-threaded, so it also runs quickly under Miri.
+        let mut slice: Arc<[String]> =
+            Arc::from(vec![String::from("a"), String::from("b")].into_boxed_slice());
+        slice.get_mut().unwrap()[1].push('c');
+        let weak = slice.downgrade();
+        assert_eq!(*weak.upgrade().unwrap(), ["a", "bc"]);
+        drop(slice);
+        assert!(weak.upgrade().is_none());
+
+        let captured = String::from("captured");
+        let f: Arc<dyn Fn() -> usize> = Arc::from(Box::new(move || captured.len()) as Box<_>);
+        assert_eq!(f.clone()(), 8);
+        assert_eq!(f(), 8);
+    }
+
+    #[test]
+    fn test_str() {
+        let a = Arc::<str>::from("hello world");
+        let b = Arc::<str>::from(String::from("hello world"));
+        assert!(!Arc::ptr_eq(&a, &b));
+        assert_eq!(*a, *b);
+        assert!(a.starts_with("hello"));
+        assert_eq!(b.split(' ').collect::<Vec<_>>(), ["hello", "world"]);
+        check_counters(a.clone().ptr, 2, 1);
+        assert_eq!(&*Arc::<str>::from(""), "");
+    }
+
+    #[test]
+    fn test_weak_count_race() {
+        static DROPS: AtomicUsize = AtomicUsize::new(0);
+        const THREADS: usize = 4;
+
+        for _ in 0..20 {
+            DROPS.store(0, Relaxed);
+            let arc = Arc::new(CountDrop(&DROPS));
+            let weak = arc.downgrade();
+            thread::scope(|s| {
+                for _ in 0..THREADS {
+                    let arc = arc.clone();
+                    s.spawn(move || {
+                        for _ in 0..100 {
+                            let w = arc.downgrade();
+                            // Every thread holds at most one, including the upgrading one,
+                            // plus `weak`.
+                            assert!((2..=THREADS + 2).contains(&Arc::weak_count(&arc)));
+                            drop(w);
+                        }
+                    });
+                }
+                s.spawn(|| {
+                    // Races with the last `Arc` being dropped.
+                    while let Some(arc) = weak.upgrade() {
+                        drop(arc.downgrade());
+                    }
+                });
+                drop(arc);
+            });
+            assert_eq!(DROPS.load(Relaxed), 1);
+            check_counters(weak.ptr, 0, 1);
+        }
+    }
+
+    #[test]
+    fn test_clone_from() {
+        let a = Arc::new(1);
+        let mut b = a.clone();
+        b.clone_from(&a);
+        check_counters(a.ptr, 2, 1);
+
+        let c = Arc::new(2);
+        b.clone_from(&c);
+        check_counters(a.ptr, 1, 1);
+        check_counters(c.ptr, 2, 1);
+        assert_eq!(*b, 2);
+    }
+
+    #[test]
+    fn test_counts() {
+        let mut arc = Arc::new(1);
+        let weak = arc.downgrade();
+        let cloned = arc.clone();
+        assert_eq!((Arc::strong_count(&arc), Arc::weak_count(&arc)), (2, 1));
+        drop((weak, cloned));
+        assert_eq!((Arc::strong_count(&arc), Arc::weak_count(&arc)), (1, 0));
+        assert!(arc.get_mut().is_some());
+        assert_eq!(Arc::weak_count(&arc), 0);
+    }
+
+    #[test]
+    fn test_cmp() {
+        static COMPARISONS: AtomicUsize = AtomicUsize::new(0);
+        #[derive(Eq)]
+        struct Key(u32);
+        impl PartialEq for Key {
+            fn eq(&self, other: &Self) -> bool {
+                COMPARISONS.fetch_add(1, Relaxed);
+                self.0 == other.0
+            }
+        }
+        impl PartialOrd for Key {
+            fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+                Some(self.cmp(other))
+            }
+        }
+        impl Ord for Key {
+            fn cmp(&self, other: &Self) -> Ordering {
+                COMPARISONS.fetch_add(1, Relaxed);
+                self.0.cmp(&other.0)
+            }
+        }
+
+        let a = Arc::new(Key(1));
+        assert!(a == a.clone());
+        assert_eq!(a.cmp(&a.clone()), Ordering::Equal);
+        assert_eq!(COMPARISONS.load(Relaxed), 0);
+
+        let b = Arc::new(Key(1));
+        let c = Arc::new(Key(2));
+        assert!(a == b);
+        assert!(a != c);
+        assert!(a < c);
+        assert_eq!(c.cmp(&b), Ordering::Greater);
+        assert_eq!(COMPARISONS.load(Relaxed), 4);
+    }
+
+    #[cfg(feature = "arc-finalizers")]
+    #[test]
+    fn test_on_last_drop() {
+        static CALLS: Mutex<Vec<(usize, i32)>> = Mutex::new(Vec::new());
+        let arc = Arc::new(7);
+        for i in 0..3 {
+            Arc::on_last_drop(&arc, move |value| CALLS.lock().push((i, *value)));
+        }
+        let clones: Vec<_> = (0..4).map(|_| arc.clone()).collect();
+        drop(arc);
+        thread::scope(|s| {
+            for clone in clones {
+                s.spawn(move || drop(clone));
+            }
+        });
+        assert_eq!(*CALLS.lock(), [(0, 7), (1, 7), (2, 7)]);
+
+        // Unsized values get them too.
+        let slice: Arc<[i32]> = Arc::from(vec![1, 2]);
+        let weak = slice.downgrade();
+        Arc::on_last_drop(&slice, |values| CALLS.lock().push((3, values.iter().sum())));
+        drop(slice);
+        assert_eq!(CALLS.lock().last(), Some(&(3, 3)));
+        assert!(weak.upgrade().is_none());
+        assert_eq!(CALLS.lock().len(), 4);
+    }
+
+    #[cfg(feature = "arc-finalizers")]
+    #[test]
+    fn test_on_last_drop_panic() {
+        let drops = std::sync::Arc::new(());
+        let arc = Arc::new(drops.clone());
+        let weak = arc.downgrade();
+        Arc::on_last_drop(&arc, |_| panic!("finalizer failed"));
+        let arc = std::panic::AssertUnwindSafe(arc);
+        assert!(catch_unwind(move || drop(arc)).is_err());
+        // The value and the allocation are still released.
+        assert_eq!(std::sync::Arc::strong_count(&drops), 1);
+        check_counters(weak.ptr, 0, 1);
+    }
+
+    #[test]
+    fn test_weak_list() {
+        let observers: Vec<_> = (0..5).map(Arc::new).collect();
+        let mut list = WeakList::new();
+        observers.iter().for_each(|observer| list.push(observer));
+
+        let [a, b, c, d, e] = observers.try_into().ok().unwrap();
+        drop((b, d));
+        let live = list.upgrade_all();
+        assert_eq!(live.iter().map(|arc| **arc).collect::<Vec<_>>(), [0, 2, 4]);
+        assert_eq!(list.len(), 5);
+        drop(live);
+
+        drop(e);
+        let live = list.upgrade_and_prune();
+        assert!(Arc::ptr_eq(&live[0], &a) && Arc::ptr_eq(&live[1], &c));
+        assert_eq!(live.len(), 2);
+        assert_eq!(list.len(), 2);
+        // Live entries keep their `Weak`, next to the implicit one.
+        check_counters(a.ptr, 2, 2);
+        drop((live, a, c));
+        assert!(list.upgrade_and_prune().is_empty());
+        assert!(list.is_empty());
+    }
+
+    #[test]
+    fn test_dedup_by_ptr() {
+        let (a, b, c) = (Arc::new(1), Arc::new(1), Arc::new(2));
+        let mut arcs = vec![
+            a.clone(),
+            b.clone(),
+            a.clone(),
+            c.clone(),
+            b.clone(),
+            a.clone(),
+        ];
+        dedup_by_ptr(&mut arcs);
+        assert_eq!(arcs.len(), 3);
+        assert!(Arc::ptr_eq(&arcs[0], &a));
+        assert!(Arc::ptr_eq(&arcs[1], &b));
+        assert!(Arc::ptr_eq(&arcs[2], &c));
+        // The removed clones were dropped.
+        assert_eq!(Arc::strong_count(&a), 2);
+
+        let mut slices: Vec<Arc<[i32]>> = vec![Arc::from(vec![1]), Arc::from(vec![1])];
+        slices.push(slices[1].clone());
+        dedup_by_ptr(&mut slices);
+        assert_eq!(slices.len(), 2);
+    }
+
+    #[test]
+    fn test_by_address() {
+        let a = Arc::new(1);
+        let b = Arc::new(1);
+        let (weak_a, weak_b) = (a.downgrade(), b.downgrade());
+
+        let mut set = std::collections::HashSet::new();
+        assert!(set.insert(ByAddress(weak_a.clone())));
+        assert!(set.insert(ByAddress(weak_b.clone())));
+        assert!(!set.insert(ByAddress(weak_a.clone())));
+        drop(a);
+        // Still the same entry after the value is gone.
+        assert!(!set.insert(ByAddress(weak_a)));
+        assert!(!set.insert(ByAddress(weak_b)));
+        assert_eq!(set.len(), 2);
+
+        assert!(ByAddress(b.clone()) == ByAddress(b.clone()));
+        assert!(ByAddress(b) != ByAddress(Arc::new(1)));
+    }
+
+    #[test]
+    fn test_make_mut() {
+        let mut a = Arc::new(1);
+        let ptr = Arc::as_ptr(&a);
+        *Arc::make_mut(&mut a) += 1;
+        assert_eq!(Arc::as_ptr(&a), ptr);
+
+        let b = a.clone();
+        let weak = a.downgrade();
+        *Arc::make_mut(&mut a) += 1;
+        assert_eq!((*a, *b), (3, 2));
+        assert!(!Arc::ptr_eq(&a, &b));
+        assert_eq!(*weak.upgrade().unwrap(), 2);
+        check_counters(a.ptr, 1, 1);
+    }
+
+    #[test]
+    fn test_make_mut_slice() {
+        let mut a: Arc<[i32]> = Arc::from(vec![1, 2, 3]);
+        let ptr = Arc::as_ptr(&a);
+        Arc::make_mut_slice(&mut a)[0] = 10;
+        assert!(ptr::addr_eq(Arc::as_ptr(&a), ptr));
+
+        let b = a.clone();
+        Arc::make_mut_slice(&mut a).reverse();
+        assert_eq!((&*a, &*b), (&[3, 2, 10][..], &[10, 2, 3][..]));
+        assert!(!Arc::ptr_eq(&a, &b));
+        check_counters(a.ptr, 1, 1);
+        check_counters(b.ptr, 1, 1);
+    }
+
+    #[test]
+    fn test_over_aligned() {
+        #[derive(Clone, Debug, PartialEq)]
+        #[repr(align(64))]
+        struct Aligned(u8);
+
+        fn is_aligned<T: ?Sized>(ptr: *const T) -> bool {
+            (ptr as *const u8).addr().is_multiple_of(64)
+        }
+
+        let arc = Arc::new(Aligned(1));
+        assert!(is_aligned(Arc::as_ptr(&arc)));
+        assert_eq!(*arc, Aligned(1));
+
+        // The paths computing the layout by hand.
+        let slice: Arc<[Aligned]> = Arc::from(vec![Aligned(2), Aligned(3)]);
+        assert!(is_aligned(Arc::as_ptr(&slice)));
+        assert_eq!(*slice, [Aligned(2), Aligned(3)]);
+        let boxed: Arc<dyn Fn() -> u8> = Arc::from(Box::new({
+            let aligned = Aligned(4);
+            // Captures all of `aligned`, not just the field.
+            move || Aligned::clone(&aligned).0
+        }) as Box<dyn Fn() -> u8>);
+        assert_eq!(std::mem::align_of_val(&*boxed), 64);
+        assert!(is_aligned(Arc::as_ptr(&boxed)));
+        assert_eq!(boxed(), 4);
+
+        let (weak, init) = Weak::new_uninit();
+        let arc = init(Aligned(5));
+        assert!(is_aligned(weak.as_ptr()));
+        assert_eq!(*arc, Aligned(5));
+    }
+
+    #[test]
+    fn test_ptr_eq() {
+        let a = Arc::new(1);
+        assert!(Arc::ptr_eq(&a, &a.clone()));
+        assert!(!Arc::ptr_eq(&a, &Arc::new(1)));
+    }
+
+    #[test]
+    fn test_project() {
+        static DROPS: AtomicUsize = AtomicUsize::new(0);
+        struct Config {
+            name: String,
+            _drop: CountDrop,
+        }
+        let arc = Arc::new(Config {
+            name: String::from("config"),
+            _drop: CountDrop(&DROPS),
+        });
+        let name = Arc::project(arc.clone(), |config| config.name.as_str());
+        check_counters(arc.ptr, 2, 1);
+        drop(arc);
+
+        let cloned = name.clone();
+        drop(name);
+        assert_eq!(DROPS.load(Relaxed), 0);
+        assert_eq!(&*cloned, "config");
+        check_counters(ProjectedArc::parent(&cloned).ptr, 1, 1);
+        drop(cloned);
+        assert_eq!(DROPS.load(Relaxed), 1);
+    }
+
+    #[test]
+    fn test_with_weak() {
+        let arc = Arc::new(1);
+        let upgraded = Arc::with_weak(&arc, |weak| {
+            check_counters(weak.ptr, 1, 1);
+            *weak.upgrade().unwrap() + 1
+        });
+        assert_eq!(upgraded, 2);
+        check_counters(arc.ptr, 1, 1);
+
+        let kept = Arc::with_weak(&arc, Weak::clone);
+        check_counters(arc.ptr, 1, 2);
+        drop(kept);
+        check_counters(arc.ptr, 1, 1);
+
+        // The lent `Weak` isn't counted, so `get_mut` on another clone can lock the count
+        // while it's being cloned.
+        let mut other = arc.clone();
+        thread::scope(|s| {
+            s.spawn(|| {
+                for _ in 0..10_000 {
+                    drop(Arc::with_weak(&arc, Weak::clone));
+                    assert!(Arc::with_weak(&arc, Weak::try_clone).is_some());
+                }
+            });
+            for _ in 0..10_000 {
+                assert!(other.get_mut().is_none());
+            }
+        });
+        check_counters(arc.ptr, 2, 1);
+
+        // That window is tiny, so also pretend a `get_mut` holds the lock for a while.
+        arc.data().weak.store(WEAK_COUNT_LOCKED_VAL, Relaxed);
+        let (kept, tried) = thread::scope(|s| {
+            s.spawn(|| {
+                thread::sleep(Duration::from_millis(10));
+                arc.data().weak.store(1, Release);
+            });
+            let kept = Arc::with_weak(&arc, Weak::clone);
+            (kept, Arc::with_weak(&arc, Weak::try_clone).unwrap())
+        });
+        check_counters(arc.ptr, 2, 3);
+        drop((kept, tried));
+        check_counters(arc.ptr, 2, 1);
+    }
+
+    #[test]
+    fn test_catch_unwind() {
+        let arc = Arc::new(1);
+        let cloned = arc.clone();
+        assert_eq!(catch_unwind(move || *cloned + 1).unwrap(), 2);
+        assert!(catch_unwind(|| {
+            if *arc == 1 {
+                panic!("unwinding with an Arc");
+            }
+        })
+        .is_err());
+        check_counters(arc.ptr, 1, 1);
+    }
+
+    #[test]
+    fn test_upgrade_limit() {
+        let arc = Arc::new(1);
+        let weak = arc.downgrade();
+        arc.data().strong.store(COUNT_LIMIT - 10, Relaxed);
+        let upgraded = AtomicUsize::new(0);
+        thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    for _ in 0..100 {
+                        if let Some(arc) = weak.upgrade() {
+                            std::mem::forget(arc);
+                            upgraded.fetch_add(1, Relaxed);
+                        }
+                    }
+                });
+            }
+        });
+        assert_eq!(upgraded.load(Relaxed), 10);
+        check_counters(arc.ptr, COUNT_LIMIT, 2);
+        // Back to the real count, so everything gets freed.
+        arc.data().strong.store(1, Relaxed);
+    }
+
+    #[test]
+    fn test_try_downgrade() {
+        let arc = Arc::new(1);
+        // Pretend a `get_mut` is in progress and never finishes.
+        arc.data().weak.store(WEAK_COUNT_LOCKED_VAL, Relaxed);
+        assert!(arc.try_downgrade(0).is_none());
+        assert!(arc.try_downgrade(1000).is_none());
+        check_counters(arc.ptr, 1, WEAK_COUNT_LOCKED_VAL);
+
+        let weak = thread::scope(|s| {
+            s.spawn(|| {
+                thread::sleep(Duration::from_millis(10));
+                arc.data().weak.store(1, Release);
+            });
+            // The caller decides when to try again.
+            loop {
+                match arc.try_downgrade(1000) {
+                    Some(weak) => break weak,
+                    None => thread::yield_now(),
+                }
+            }
+        });
+        check_counters(arc.ptr, 1, 2);
+        let weak2 = arc.try_downgrade(0).unwrap();
+        assert!(weak.ptr_eq(&weak2));
+        check_counters(arc.ptr, 1, 3);
+    }
+
+    #[test]
+    fn test_try_clone() {
+        let arc = Arc::new(1);
+        let weak = arc.downgrade();
+        let clone = weak.try_clone().unwrap();
+        check_counters(arc.ptr, 1, 3);
+        drop(clone);
+
+        arc.data().weak.store(COUNT_LIMIT - 1, Relaxed);
+        let clone = weak.try_clone().unwrap();
+        assert!(weak.try_clone().is_none());
+        assert!(clone.try_clone().is_none());
+        check_counters(arc.ptr, 1, COUNT_LIMIT);
+        // Back to the real count, so everything gets freed.
+        std::mem::forget(clone);
+        arc.data().weak.store(2, Relaxed);
+    }
+
+    #[test]
+    fn test_weak_new_uninit() {
+        static DROPS: AtomicUsize = AtomicUsize::new(0);
+        let (weak, init) = Weak::<(u32, CountDrop)>::new_uninit();
+        assert!(weak.upgrade().is_none());
+        check_counters(weak.ptr, 0, 2);
+
+        let upgraded = thread::scope(|s| {
+            let upgrading = s.spawn(|| loop {
+                if let Some(arc) = weak.upgrade() {
+                    break arc.0;
+                }
+                thread::yield_now();
+            });
+            let arc = init((7, CountDrop(&DROPS)));
+            check_counters(arc.ptr, 1, 2);
+            let upgraded = upgrading.join().unwrap();
+            drop(arc);
+            upgraded
+        });
+        assert_eq!(upgraded, 7);
+        assert_eq!(DROPS.load(Relaxed), 1);
+        assert!(weak.is_expired());
+
+        // Never initialized, nothing to drop.
+        let (weak, init) = Weak::<CountDrop>::new_uninit();
+        drop(init);
+        check_counters(weak.ptr, 0, 1);
+        drop(weak);
+        assert_eq!(DROPS.load(Relaxed), 1);
+    }
+
+    #[test]
+    fn test_weak_self() {
+        struct Node {
+            this: WeakSelf<Node>,
+            name: &'static str,
+        }
+
+        let node = Arc::new_cyclic(|weak| {
+            assert!(weak.upgrade().is_none());
+            let mut this = WeakSelf::new();
+            this.set(weak);
+            Node { this, name: "root" }
+        });
+        check_counters(node.ptr, 1, 2);
+
+        let handle = node.this.arc();
+        assert!(Arc::ptr_eq(&node, &handle));
+        assert_eq!(handle.name, "root");
+        check_counters(node.ptr, 2, 2);
+        drop(node);
+        let weak = handle.downgrade();
+        drop(handle);
+        // Only the `Weak` outside is left, the one in `WeakSelf` was dropped with the value.
+        check_counters(weak.ptr, 0, 1);
+
+        let unset = Arc::new(Node {
+            this: WeakSelf::new(),
+            name: "unset",
+        });
+        assert!(catch_unwind(std::panic::AssertUnwindSafe(|| unset.this.arc())).is_err());
+    }
+
+    #[test]
+    fn test_is_expired() {
+        let arc = Arc::new(1);
+        let cloned = arc.clone();
+        let weak = arc.downgrade();
+        drop(arc);
+        assert!(!weak.is_expired());
+        drop(cloned);
+        assert!(weak.is_expired());
+        assert!(weak.upgrade().is_none());
+
+        let (weak, init) = Weak::new_uninit();
+        assert!(weak.is_expired());
+        let arc = init(2);
+        assert!(!weak.is_expired());
+        drop(arc);
+        assert!(weak.is_expired());
+    }
+
+    #[test]
+    fn test_get_mut_unchecked() {
+        let mut arc = Arc::new((0, String::new()));
+        let value = unsafe { Arc::get_mut_unchecked(&mut arc) };
+        value.0 = 1;
+        value.1.push_str("init");
+        let cloned = arc.clone();
+        assert_eq!(*cloned, (1, String::from("init")));
+        check_counters(arc.ptr, 2, 1);
+    }
+
+    #[test]
+    fn test_new_uninit() {
+        let mut uninit = Arc::<[u32; 4]>::new_uninit();
+        uninit.get_mut().unwrap().write([1, 2, 3, 4]);
+        let arc = unsafe { Arc::assume_init(uninit) };
+        check_counters(arc.ptr, 1, 1);
+
+        let weak = arc.downgrade();
+        assert_eq!(*weak.upgrade().unwrap(), [1, 2, 3, 4]);
+        drop(arc);
+        assert!(weak.upgrade().is_none());
+    }
+
     static A_B_DROP_COUNT: AtomicUsize = AtomicUsize::new(0);
 
     struct A {
@@ -294,4 +1724,92 @@ mod test {
 
         assert_eq!(A_B_DROP_COUNT.load(Relaxed), 2);
     }
+
+    #[cfg(feature = "shared-ptr")]
+    #[test]
+    fn test_shared_ptr() {
+        use crate::primitives::mutex::Mutex;
+
+        fn count_up<P: SharedPtr<Mutex<u32>> + Send + 'static>() -> u32 {
+            let shared = P::new(Mutex::new(0));
+            let handles: Vec<_> = (0..4)
+                .map(|_| {
+                    let shared = shared.clone();
+                    spawn(move || *shared.lock() += 1)
+                })
+                .collect();
+            handles.into_iter().for_each(|h| h.join().unwrap());
+            assert!(P::ptr_eq(&shared, &shared.clone()));
+            let count = *shared.lock();
+            count
+        }
+
+        assert_eq!(count_up::<Arc<Mutex<u32>>>(), 4);
+        assert_eq!(count_up::<std::sync::Arc<Mutex<u32>>>(), 4);
+    }
+
+    #[test]
+    fn test_clear_link() {
+        static DROPS: AtomicUsize = AtomicUsize::new(0);
+        type Link = Arc<RefCell<Option<Arc<Node>>>>;
+        struct Node {
+            next: Link,
+            _drop: CountDrop,
+        }
+
+        let a = Arc::new(Node {
+            next: Arc::new(RefCell::new(None)),
+            _drop: CountDrop(&DROPS),
+        });
+        let b = Arc::new(Node {
+            next: Arc::new(RefCell::new(Some(a.clone()))),
+            _drop: CountDrop(&DROPS),
+        });
+        *a.next.borrow_mut() = Some(b.clone());
+        let link = a.next.clone();
+        drop((a, b));
+        assert_eq!(DROPS.load(Relaxed), 0);
+
+        link.clear_link();
+        assert_eq!(DROPS.load(Relaxed), 2);
+        assert!(link.borrow().is_none());
+        check_counters(link.ptr, 1, 1);
+    }
+
+    #[cfg(feature = "arc-arena")]
+    #[test]
+    fn test_arena() {
+        static DROPS: AtomicUsize = AtomicUsize::new(0);
+
+        let arena = ArcArena::new();
+        for i in 0..1000 {
+            let arc = arena.alloc((i, CountDrop(&DROPS)));
+            let weak = arc.downgrade();
+            assert_eq!(arc.0, i);
+            drop(arc);
+            assert!(weak.upgrade().is_none());
+        }
+        assert_eq!(DROPS.load(Relaxed), 1000);
+        assert_eq!(arena.allocated(), 1);
+
+        let live: Vec<_> = (0..10)
+            .map(|i| arena.alloc((i, CountDrop(&DROPS))))
+            .collect();
+        assert_eq!(arena.allocated(), 10);
+        let addrs: Vec<_> = live.iter().map(Arc::as_ptr).collect();
+        drop(live);
+        let reused: Vec<_> = (0..10)
+            .map(|i| arena.alloc((i, CountDrop(&DROPS))))
+            .collect();
+        assert_eq!(arena.allocated(), 10);
+        assert!(reused.iter().all(|arc| addrs.contains(&Arc::as_ptr(arc))));
+
+        // The `Arc`s keep the slots alive after the arena is gone.
+        drop(arena);
+        let weak = reused[9].downgrade();
+        assert_eq!(reused[9].0, 9);
+        drop(reused);
+        assert_eq!(DROPS.load(Relaxed), 1020);
+        drop(weak);
+    }
 }
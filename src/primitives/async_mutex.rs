@@ -0,0 +1,247 @@
+use std::{
+    cell::UnsafeCell,
+    collections::VecDeque,
+    future::Future,
+    ops::{Deref, DerefMut},
+    pin::Pin,
+    sync::atomic::{AtomicU32, Ordering::*},
+    task::{Context, Poll, Waker},
+};
+
+use super::mutex::Mutex;
+
+pub struct AsyncMutex<T> {
+    // 0: unlocked
+    // 1: locked, no waiting tasks
+    // 2: locked, some waiting tasks
+    state: AtomicU32,
+    waiters: Mutex<Waiters>,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T> Sync for AsyncMutex<T> where T: Send {}
+
+/// Tasks waiting for the lock, in arrival order.
+/// Only the front one is woken up when the lock is released.
+struct Waiters {
+    queue: VecDeque<(u64, Waker)>,
+    next_id: u64,
+}
+
+impl Waiters {
+    fn register(&mut self, id: &mut Option<u64>, waker: &Waker) {
+        if let Some(id) = *id {
+            if let Some((_, w)) = self.queue.iter_mut().find(|(i, _)| *i == id) {
+                w.clone_from(waker);
+                return;
+            }
+        }
+        let new_id = self.next_id;
+        self.next_id += 1;
+        self.queue.push_back((new_id, waker.clone()));
+        *id = Some(new_id);
+    }
+
+    fn remove(&mut self, id: u64) {
+        self.queue.retain(|(i, _)| *i != id);
+    }
+
+    fn front(&self) -> Option<Waker> {
+        self.queue.front().map(|(_, w)| w.clone())
+    }
+}
+
+impl<T> AsyncMutex<T> {
+    pub const fn new(data: T) -> Self {
+        Self {
+            state: AtomicU32::new(0),
+            waiters: Mutex::new(Waiters {
+                queue: VecDeque::new(),
+                next_id: 0,
+            }),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    /// Unlike `Mutex::lock`, waiting doesn't block the thread,
+    /// so the guard can be held across `.await` points.
+    pub async fn lock(&self) -> AsyncMutexGuard<'_, T> {
+        Lock {
+            mutex: self,
+            id: None,
+        }
+        .await
+    }
+
+    fn wake_front(&self) {
+        // Wake outside of the queue lock, the waker might run arbitrary code.
+        let waker = self.waiters.lock().front();
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+struct Lock<'a, T> {
+    mutex: &'a AsyncMutex<T>,
+    /// Position in the wait queue, if registered.
+    id: Option<u64>,
+}
+
+impl<'a, T> Future for Lock<'a, T> {
+    type Output = AsyncMutexGuard<'a, T>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        let mutex = this.mutex;
+
+        if this.id.is_none() && mutex.state.compare_exchange(0, 1, Acquire, Relaxed).is_ok() {
+            return Poll::Ready(AsyncMutexGuard { mutex });
+        }
+
+        // Register before marking the lock as contended, so the unlock can't miss us.
+        let mut waiters = mutex.waiters.lock();
+        waiters.register(&mut this.id, cx.waker());
+        if mutex.state.swap(2, Acquire) != 0 {
+            return Poll::Pending;
+        }
+        if let Some(id) = this.id.take() {
+            waiters.remove(id);
+        }
+        Poll::Ready(AsyncMutexGuard { mutex })
+    }
+}
+
+impl<T> Drop for Lock<'_, T> {
+    fn drop(&mut self) {
+        // Cancelled while waiting. We might have been woken up already,
+        // so make sure the next task doesn't miss the unlock instead.
+        if let Some(id) = self.id {
+            let mut waiters = self.mutex.waiters.lock();
+            waiters.remove(id);
+            if waiters.queue.is_empty() {
+                return;
+            }
+            drop(waiters);
+            if let Err(0) = self.mutex.state.compare_exchange(1, 2, Relaxed, Relaxed) {
+                self.mutex.wake_front();
+            }
+        }
+    }
+}
+
+pub struct AsyncMutexGuard<'a, T> {
+    mutex: &'a AsyncMutex<T>,
+}
+
+unsafe impl<T> Sync for AsyncMutexGuard<'_, T> where T: Sync {}
+
+impl<T> Deref for AsyncMutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.mutex.data.get() }
+    }
+}
+
+impl<T> DerefMut for AsyncMutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.mutex.data.get() }
+    }
+}
+
+impl<T> Drop for AsyncMutexGuard<'_, T> {
+    fn drop(&mut self) {
+        if self.mutex.state.swap(0, Release) == 2 {
+            self.mutex.wake_front();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::AsyncMutex;
+    use std::{
+        future::Future,
+        pin::pin,
+        sync::Arc,
+        task::{Context, Poll, Wake, Waker},
+        thread::{self, Thread},
+    };
+
+    struct ThreadWaker(Thread);
+
+    impl Wake for ThreadWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    fn block_on<F: Future>(fut: F) -> F::Output {
+        let mut fut = pin!(fut);
+        let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        loop {
+            match fut.as_mut().poll(&mut cx) {
+                Poll::Ready(v) => return v,
+                Poll::Pending => thread::park(),
+            }
+        }
+    }
+
+    /// Gives other tasks a chance to run, while still holding whatever we hold.
+    async fn yield_now() {
+        let mut yielded = false;
+        std::future::poll_fn(|cx| {
+            if yielded {
+                return Poll::Ready(());
+            }
+            yielded = true;
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        })
+        .await
+    }
+
+    #[test]
+    fn test() {
+        let mutex = AsyncMutex::new(0);
+        thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    block_on(async {
+                        for _ in 0..100 {
+                            let mut g = mutex.lock().await;
+                            let val = *g;
+                            yield_now().await;
+                            *g = val + 1;
+                        }
+                    })
+                });
+            }
+        });
+        assert_eq!(*block_on(mutex.lock()), 400);
+    }
+
+    #[test]
+    fn test_cancelled_waiter() {
+        let mutex = AsyncMutex::new(0);
+        let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+        let mut cx = Context::from_waker(&waker);
+
+        let g = block_on(mutex.lock());
+        {
+            let mut cancelled = pin!(mutex.lock());
+            assert!(cancelled.as_mut().poll(&mut cx).is_pending());
+        }
+        thread::scope(|s| {
+            let waiter = s.spawn(|| *block_on(mutex.lock()) + 1);
+            // Let the waiter queue up behind the cancelled task.
+            while mutex.waiters.lock().queue.is_empty() {
+                thread::yield_now();
+            }
+            drop(g);
+            assert_eq!(waiter.join().unwrap(), 1);
+        });
+    }
+}
@@ -0,0 +1,34 @@
+use std::ops::Deref;
+
+/// With the `cache-padded` feature, gives `T` a cache line of its own,
+/// so that locks packed next to each other don't contend on the same line.
+/// Off by default, since it blows every small lock up to a whole cache line.
+#[cfg_attr(feature = "cache-padded", repr(align(64)))]
+pub(crate) struct CachePadded<T>(T);
+
+impl<T> CachePadded<T> {
+    pub(crate) const fn new(value: T) -> Self {
+        Self(value)
+    }
+}
+
+impl<T> Deref for CachePadded<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::primitives::{mutex::Mutex, rwlock::RwLock};
+    use std::mem::align_of;
+
+    #[test]
+    fn test() {
+        let padded = cfg!(feature = "cache-padded");
+        assert_eq!(align_of::<Mutex<u8>>() == 64, padded);
+        assert_eq!(align_of::<RwLock<u8>>() == 64, padded);
+    }
+}
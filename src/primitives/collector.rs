@@ -0,0 +1,90 @@
+use std::mem;
+
+use super::{condvar::Condvar, mutex::Mutex};
+
+/// Fan-in of a known number of results, e.g. from worker threads:
+/// workers `push` theirs, and `blocking_collect` waits for all of them.
+pub struct Collector<T> {
+    state: Mutex<State<T>>,
+    condvar: Condvar,
+    expected: usize,
+}
+
+struct State<T> {
+    results: Vec<T>,
+    /// Stays put when `results` are taken out.
+    pushed: usize,
+}
+
+impl<T> Collector<T> {
+    pub fn new(expected: usize) -> Self {
+        Self {
+            state: Mutex::new(State {
+                results: Vec::with_capacity(expected),
+                pushed: 0,
+            }),
+            condvar: Condvar::new(),
+            expected,
+        }
+    }
+
+    /// # Panics
+    ///
+    /// If all expected results are already there.
+    pub fn push(&self, result: T) {
+        let mut state = self.state.lock();
+        assert!(state.pushed < self.expected, "too many results pushed");
+        state.results.push(result);
+        state.pushed += 1;
+        // Nobody cares about partial results, only wake the collector once it's done.
+        if state.pushed == self.expected {
+            self.condvar.notify_all();
+        }
+    }
+
+    /// Blocks until all expected results are pushed, and returns them in the order they arrived.
+    /// Takes them out, so collecting again returns an empty `Vec` right away.
+    pub fn blocking_collect(&self) -> Vec<T> {
+        let mut state = self
+            .condvar
+            .wait_while(self.state.lock(), |state| state.pushed < self.expected);
+        mem::take(&mut state.results)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Collector;
+    use std::{thread, time::Duration};
+
+    #[test]
+    fn test() {
+        let collector = Collector::new(8);
+        thread::scope(|s| {
+            for i in 0..8 {
+                let collector = &collector;
+                s.spawn(move || collector.push(i * i));
+            }
+        });
+        let mut results = collector.blocking_collect();
+        results.sort();
+        assert_eq!(results, (0..8).map(|i| i * i).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_collect_while_pushing() {
+        let collector = Collector::new(4);
+        let results = thread::scope(|s| {
+            for i in 0..4 {
+                let collector = &collector;
+                s.spawn(move || {
+                    thread::sleep(Duration::from_millis(10 * i));
+                    collector.push(i);
+                });
+            }
+            collector.blocking_collect()
+        });
+        assert_eq!(results, [0, 1, 2, 3]);
+        assert!(collector.blocking_collect().is_empty());
+    }
+}
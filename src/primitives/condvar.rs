@@ -1,47 +1,173 @@
-use super::mutex::MutexGuard;
-use atomic_wait::{wait, wake_all, wake_one};
-use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering::Relaxed};
+use super::{futex::wait_timeout, mutex::MutexGuard};
+use atomic_wait::{wait, wake_all};
+use std::{
+    sync::atomic::{
+        AtomicU32,
+        Ordering::{Acquire, Relaxed, Release},
+    },
+    time::Instant,
+};
 
+/// Waiters are woken up in the order they started waiting, `notify_one` always picks
+/// the one that waited longest.
+///
+/// Every waiter draws a ticket, and a notification serves the oldest ticket. All waiters
+/// sleep on the same counter, so each notification briefly wakes all of them to check
+/// whether it's their turn.
 pub struct Condvar {
-    counter: AtomicU32,
-    waiters_count: AtomicUsize,
+    /// Ticket handed to the next waiter.
+    next_ticket: AtomicU32,
+    /// Waiters with a ticket below this one are notified.
+    now_serving: AtomicU32,
 }
 
 impl Condvar {
     pub const fn new() -> Self {
         Self {
-            counter: AtomicU32::new(0),
-            waiters_count: AtomicUsize::new(0),
+            next_ticket: AtomicU32::new(0),
+            now_serving: AtomicU32::new(0),
         }
     }
 
     pub fn notify_one(&self) {
-        if self.waiters_count.load(Relaxed) != 0 {
-            self.counter.fetch_add(1, Relaxed);
-            wake_one(&self.counter);
+        let mut serving = self.now_serving.load(Relaxed);
+        while serving != self.next_ticket.load(Relaxed) {
+            match self.now_serving.compare_exchange_weak(
+                serving,
+                serving.wrapping_add(1),
+                Release,
+                Relaxed,
+            ) {
+                Ok(_) => {
+                    wake_all(&self.now_serving);
+                    return;
+                }
+                Err(e) => serving = e,
+            }
         }
     }
 
     pub fn notify_all(&self) {
-        if self.waiters_count.load(Relaxed) != 0 {
-            self.counter.fetch_add(1, Relaxed);
-            wake_all(&self.counter);
-        }
+        self.serve_all();
     }
 
+    /// Can wake up spuriously, e.g. when notified right before the condition changed again,
+    /// so callers have to check their condition in a loop.
     pub fn wait<'a, T>(&self, guard: MutexGuard<'a, T>) -> MutexGuard<'a, T> {
-        self.waiters_count.fetch_add(1, Relaxed);
+        let ticket = self.take_ticket();
+
+        let mutex = guard.mutex;
+        drop(guard);
+
+        // The futex wait can also return early on its own, e.g. when interrupted by a signal.
+        loop {
+            let serving = self.now_serving.load(Acquire);
+            if served(serving, ticket) {
+                break;
+            }
+            wait(&self.now_serving, serving);
+        }
+
+        mutex.lock()
+    }
+
+    /// Waits for as long as `cond` returns true, so spurious wakeups are taken care of.
+    pub fn wait_while<'a, T>(
+        &self,
+        mut guard: MutexGuard<'a, T>,
+        mut cond: impl FnMut(&mut T) -> bool,
+    ) -> MutexGuard<'a, T> {
+        while cond(&mut guard) {
+            guard = self.wait(guard);
+        }
+        guard
+    }
 
-        let counter = self.counter.load(Relaxed);
+    /// Like `wait`, but gives up once `deadline` passes.
+    /// Wakeups that aren't notifications don't extend the wait, and callers looping on a
+    /// condition can pass the same deadline again.
+    ///
+    /// A ticket can't leave the middle of the queue, so a waiter timing out wakes up
+    /// everyone else waiting instead. Otherwise a later notification could go to it and
+    /// be lost.
+    pub fn wait_deadline<'a, T>(
+        &self,
+        guard: MutexGuard<'a, T>,
+        deadline: Instant,
+    ) -> (MutexGuard<'a, T>, WaitTimeoutResult) {
+        let ticket = self.take_ticket();
 
         let mutex = guard.mutex;
         drop(guard);
 
-        wait(&self.counter, counter);
+        let mut timed_out = false;
+        loop {
+            let serving = self.now_serving.load(Acquire);
+            if served(serving, ticket) {
+                break;
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                self.serve_all();
+                timed_out = true;
+                break;
+            }
+            wait_timeout(&self.now_serving, serving, deadline - now);
+        }
 
-        self.waiters_count.fetch_sub(1, Relaxed);
+        (mutex.lock(), WaitTimeoutResult(timed_out))
+    }
 
-        mutex.lock()
+    #[cfg(test)]
+    pub(crate) fn waiter_count(&self) -> usize {
+        let serving = self.now_serving.load(Relaxed);
+        self.next_ticket.load(Relaxed).wrapping_sub(serving) as usize
+    }
+
+    /// Taken while the caller still holds its mutex, so no notification can be missed.
+    fn take_ticket(&self) -> u32 {
+        self.next_ticket.fetch_add(1, Relaxed)
+    }
+
+    fn serve_all(&self) {
+        let mut serving = self.now_serving.load(Relaxed);
+        loop {
+            let next = self.next_ticket.load(Relaxed);
+            if serving == next {
+                return;
+            }
+            match self
+                .now_serving
+                .compare_exchange_weak(serving, next, Release, Relaxed)
+            {
+                Ok(_) => {
+                    wake_all(&self.now_serving);
+                    return;
+                }
+                Err(e) => serving = e,
+            }
+        }
+    }
+}
+
+/// Tickets wrap around, compared relative to each other.
+fn served(serving: u32, ticket: u32) -> bool {
+    (serving.wrapping_sub(ticket) as i32) > 0
+}
+
+/// Same as `std::sync::WaitTimeoutResult`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WaitTimeoutResult(bool);
+
+impl WaitTimeoutResult {
+    pub fn timed_out(&self) -> bool {
+        self.0
+    }
+}
+
+impl Default for Condvar {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
@@ -49,7 +175,11 @@ impl Condvar {
 mod test {
     use super::super::mutex::Mutex;
     use super::Condvar;
-    use std::{thread, time::Duration};
+    use std::{
+        sync::atomic::AtomicU32,
+        thread,
+        time::{Duration, Instant},
+    };
 
     #[test]
     fn test() {
@@ -76,4 +206,195 @@ mod test {
 
         assert!(wakeups < 10);
     }
+
+    #[test]
+    fn test_wait_while() {
+        let mutex = Mutex::new(0);
+        let condvar = Condvar::new();
+        thread::scope(|s| {
+            s.spawn(|| {
+                for _ in 0..10 {
+                    *mutex.lock() += 1;
+                    condvar.notify_one();
+                }
+            });
+            let g = condvar.wait_while(mutex.lock(), |n| *n < 10);
+            assert_eq!(*g, 10);
+        });
+    }
+
+    #[test]
+    fn test_wait_deadline() {
+        let mutex = Mutex::new(false);
+        let condvar = Condvar::new();
+
+        let (g, result) = condvar.wait_deadline(mutex.lock(), Instant::now());
+        assert!(result.timed_out());
+        drop(g);
+
+        thread::scope(|s| {
+            let start = Instant::now();
+            let deadline = start + Duration::from_millis(300);
+            let condvar = &condvar;
+            s.spawn(move || {
+                // Wake the waiter up a bunch of times without the condition changing.
+                while Instant::now() < deadline + Duration::from_millis(100) {
+                    condvar.notify_all();
+                    thread::sleep(Duration::from_millis(10));
+                }
+            });
+
+            let mut g = mutex.lock();
+            let mut wakeups = 0;
+            while !*g {
+                let (new_g, result) = condvar.wait_deadline(g, deadline);
+                g = new_g;
+                if result.timed_out() {
+                    break;
+                }
+                wakeups += 1;
+            }
+            assert!(wakeups > 1);
+            assert!(Instant::now() >= deadline);
+            assert!(start.elapsed() < Duration::from_millis(400));
+        });
+    }
+
+    #[test]
+    fn test_timeout_in_queue() {
+        let mutex = Mutex::new(false);
+        let condvar = Condvar::new();
+
+        thread::scope(|s| {
+            let g = mutex.lock();
+            // Queued behind a waiter that's about to time out.
+            let waiter = s.spawn(|| {
+                while condvar.waiter_count() == 0 {
+                    thread::yield_now();
+                }
+                drop(condvar.wait_while(mutex.lock(), |ready| !*ready));
+            });
+            let deadline = Instant::now() + Duration::from_millis(100);
+            let (g, result) = condvar.wait_deadline(g, deadline);
+            assert!(result.timed_out());
+            drop(g);
+
+            while condvar.waiter_count() == 0 {
+                thread::yield_now();
+            }
+            // Has to reach the waiter, not the ticket that timed out.
+            *mutex.lock() = true;
+            condvar.notify_one();
+            waiter.join().unwrap();
+        });
+        assert_eq!(condvar.waiter_count(), 0);
+    }
+
+    #[test]
+    fn test_ticket_wraparound() {
+        let mutex = Mutex::new(0);
+        let condvar = Condvar {
+            next_ticket: AtomicU32::new(u32::MAX - 2),
+            now_serving: AtomicU32::new(u32::MAX - 2),
+        };
+        thread::scope(|s| {
+            s.spawn(|| {
+                for _ in 0..10 {
+                    *mutex.lock() += 1;
+                    condvar.notify_one();
+                    thread::sleep(Duration::from_millis(5));
+                }
+            });
+            drop(condvar.wait_while(mutex.lock(), |n| *n < 10));
+        });
+        assert_eq!(condvar.waiter_count(), 0);
+    }
+
+    #[test]
+    fn test_spurious_wakeups() {
+        let mutex = Mutex::new(false);
+        let condvar = Condvar::new();
+
+        thread::scope(|s| {
+            s.spawn(|| {
+                // Notifications without the condition changing look spurious to the waiter.
+                for _ in 0..5 {
+                    thread::sleep(Duration::from_millis(10));
+                    condvar.notify_all();
+                }
+                *mutex.lock() = true;
+                condvar.notify_all();
+            });
+
+            let mut ready = mutex.lock();
+            let mut wakeups = 0;
+            while !*ready {
+                ready = condvar.wait(ready);
+                wakeups += 1;
+            }
+            assert!(*ready);
+            assert!(wakeups >= 1);
+        });
+        assert_eq!(condvar.waiter_count(), 0);
+    }
+
+    #[test]
+    fn test_fifo() {
+        let mutex = Mutex::new(());
+        let condvar = Condvar::new();
+        let woken = Mutex::new(Vec::new());
+
+        thread::scope(|s| {
+            for i in 0..5 {
+                let (mutex, condvar, woken) = (&mutex, &condvar, &woken);
+                s.spawn(move || {
+                    let g = mutex.lock();
+                    drop(condvar.wait(g));
+                    woken.lock().push(i);
+                });
+                // Make sure waiters queue up in order.
+                while condvar.waiter_count() != i + 1 {
+                    thread::yield_now();
+                }
+            }
+            for i in 1..=5 {
+                condvar.notify_one();
+                while woken.lock().len() != i {
+                    thread::yield_now();
+                }
+            }
+        });
+        assert_eq!(*woken.lock(), [0, 1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn test_futex_noise() {
+        let mutex = Mutex::new(false);
+        let condvar = Condvar::new();
+
+        let returns = thread::scope(|s| {
+            let waiter = s.spawn(|| {
+                let mut returns = 0;
+                let mut ready = mutex.lock();
+                while !*ready {
+                    ready = condvar.wait(ready);
+                    returns += 1;
+                }
+                returns
+            });
+            while condvar.waiter_count() == 0 {
+                thread::yield_now();
+            }
+            // Futex wakeups that aren't notifications, the waiter's ticket isn't served.
+            for _ in 0..10 {
+                atomic_wait::wake_all(&condvar.now_serving);
+                thread::sleep(Duration::from_millis(5));
+            }
+            *mutex.lock() = true;
+            condvar.notify_one();
+            waiter.join().unwrap()
+        });
+        // Only the real notification made `wait` lock the mutex again.
+        assert_eq!(returns, 1);
+    }
 }
@@ -0,0 +1,222 @@
+use std::sync::atomic::{AtomicU32, Ordering::*};
+
+use atomic_wait::{wait, wake_all, wake_one};
+
+/// Manual-reset event: once set, lets every waiter through until it's reset again.
+pub struct Event {
+    // 0: not set
+    // 1: set
+    state: AtomicU32,
+}
+
+impl Event {
+    pub const fn new() -> Self {
+        Self {
+            state: AtomicU32::new(0),
+        }
+    }
+
+    pub fn wait(&self) {
+        while self.state.load(Acquire) == 0 {
+            wait(&self.state, 0);
+        }
+    }
+
+    pub fn set(&self) {
+        if self.state.swap(1, Release) == 0 {
+            wake_all(&self.state);
+        }
+    }
+
+    pub fn reset(&self) {
+        self.state.store(0, Relaxed);
+    }
+
+    pub fn is_set(&self) -> bool {
+        self.state.load(Acquire) == 1
+    }
+}
+
+impl Default for Event {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Auto-reset event: every `set` lets exactly one waiter through.
+/// Setting it while nobody waits lets the next waiter through, but sets don't add up.
+pub struct AutoResetEvent {
+    /// Lower 16 bits: waiters that haven't been let through yet.
+    /// Upper 16 bits: sets that haven't been taken by a waiter yet, at most one more than
+    /// there are waiters.
+    state: AtomicU32,
+}
+
+const WAITER: u32 = 1;
+const WAITERS: u32 = 0xffff;
+const SET: u32 = 1 << 16;
+
+impl AutoResetEvent {
+    pub const fn new() -> Self {
+        Self {
+            state: AtomicU32::new(0),
+        }
+    }
+
+    pub fn wait(&self) {
+        let mut registered = false;
+        let mut state = self.state.load(Relaxed);
+        loop {
+            if state >= SET {
+                let new = state - SET - if registered { WAITER } else { 0 };
+                match self.state.compare_exchange(state, new, Acquire, Relaxed) {
+                    Ok(_) => return,
+                    Err(e) => state = e,
+                }
+                continue;
+            }
+            // Count ourselves in before sleeping, so a `set` knows there's someone to wake.
+            if !registered {
+                assert!(state & WAITERS != WAITERS, "too many waiters");
+                match self
+                    .state
+                    .compare_exchange(state, state + WAITER, Relaxed, Relaxed)
+                {
+                    Ok(_) => {
+                        registered = true;
+                        state += WAITER;
+                    }
+                    Err(e) => {
+                        state = e;
+                        continue;
+                    }
+                }
+            }
+            wait(&self.state, state);
+            state = self.state.load(Relaxed);
+        }
+    }
+
+    pub fn set(&self) {
+        let mut state = self.state.load(Relaxed);
+        loop {
+            let waiters = state & WAITERS;
+            // Every waiter already has a set waiting for it, or nobody waits and one is kept.
+            if state >> 16 >= waiters.max(1) {
+                return;
+            }
+            match self
+                .state
+                .compare_exchange(state, state + SET, Release, Relaxed)
+            {
+                Ok(_) => {
+                    if waiters != 0 {
+                        wake_one(&self.state);
+                    }
+                    return;
+                }
+                Err(e) => state = e,
+            }
+        }
+    }
+}
+
+impl Default for AutoResetEvent {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{AutoResetEvent, Event};
+    use std::{
+        sync::atomic::{AtomicUsize, Ordering::Relaxed},
+        thread,
+        time::{Duration, Instant},
+    };
+
+    #[test]
+    fn test() {
+        let event = Event::new();
+        let released = AtomicUsize::new(0);
+        thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    event.wait();
+                    released.fetch_add(1, Relaxed);
+                });
+            }
+            thread::sleep(Duration::from_millis(50));
+            assert_eq!(released.load(Relaxed), 0);
+            event.set();
+        });
+        assert_eq!(released.load(Relaxed), 4);
+
+        // Stays set for later waiters.
+        event.wait();
+        event.reset();
+        assert!(!event.is_set());
+        thread::scope(|s| {
+            let waiter = s.spawn(|| event.wait());
+            thread::sleep(Duration::from_millis(50));
+            assert!(!waiter.is_finished());
+            event.set();
+        });
+    }
+
+    #[test]
+    fn test_auto_reset() {
+        let event = AutoResetEvent::new();
+        let released = AtomicUsize::new(0);
+        thread::scope(|s| {
+            for _ in 0..3 {
+                s.spawn(|| {
+                    event.wait();
+                    released.fetch_add(1, Relaxed);
+                });
+            }
+            for i in 1..=3 {
+                event.set();
+                while released.load(Relaxed) < i {
+                    thread::yield_now();
+                }
+                // Only one waiter got through.
+                thread::sleep(Duration::from_millis(20));
+                assert_eq!(released.load(Relaxed), i);
+            }
+        });
+
+        // A set without waiters is kept for the next one, but only once.
+        event.set();
+        event.set();
+        event.wait();
+        assert_eq!(event.state.load(Relaxed), 0);
+    }
+
+    #[test]
+    fn test_auto_reset_back_to_back() {
+        let event = AutoResetEvent::new();
+        let released = AtomicUsize::new(0);
+        thread::scope(|s| {
+            for _ in 0..2 {
+                s.spawn(|| {
+                    event.wait();
+                    released.fetch_add(1, Relaxed);
+                });
+            }
+            while event.state.load(Relaxed) != 2 {
+                thread::yield_now();
+            }
+            // The second set comes before the first waiter got through.
+            event.set();
+            event.set();
+            let start = Instant::now();
+            while released.load(Relaxed) < 2 {
+                assert!(start.elapsed() < Duration::from_secs(5), "a set was lost");
+                thread::yield_now();
+            }
+        });
+        assert_eq!(event.state.load(Relaxed), 0);
+    }
+}
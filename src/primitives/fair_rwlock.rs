@@ -0,0 +1,171 @@
+use std::{
+    cell::UnsafeCell,
+    ops::{Deref, DerefMut},
+    sync::atomic::{AtomicU32, Ordering::*},
+};
+
+use atomic_wait::{wait, wake_all};
+
+/// Reader-writer lock serving readers and writers strictly in arrival order.
+/// Readers that arrive one after another, with no writer between them, share the lock,
+/// so neither readers nor writers can be starved.
+pub struct FairRwLock<T> {
+    /// Ticket handed out to the next reader or writer.
+    next_ticket: AtomicU32,
+    /// A reader may enter once this reaches its ticket.
+    read_turn: AtomicU32,
+    /// A writer may enter once this reaches its ticket.
+    write_turn: AtomicU32,
+    value: UnsafeCell<T>,
+}
+
+unsafe impl<T> Sync for FairRwLock<T> where T: Send + Sync {}
+
+impl<T> FairRwLock<T> {
+    pub const fn new(data: T) -> Self {
+        Self {
+            next_ticket: AtomicU32::new(0),
+            read_turn: AtomicU32::new(0),
+            write_turn: AtomicU32::new(0),
+            value: UnsafeCell::new(data),
+        }
+    }
+
+    pub fn read(&self) -> FairReadGuard<'_, T> {
+        let ticket = self.next_ticket.fetch_add(1, Relaxed);
+        wait_for_turn(&self.read_turn, ticket);
+        // Let the next reader in too, in case it arrived right after us.
+        self.read_turn.fetch_add(1, Release);
+        wake_all(&self.read_turn);
+        FairReadGuard { rwlock: self }
+    }
+
+    pub fn write(&self) -> FairWriteGuard<'_, T> {
+        let ticket = self.next_ticket.fetch_add(1, Relaxed);
+        wait_for_turn(&self.write_turn, ticket);
+        FairWriteGuard { rwlock: self }
+    }
+}
+
+fn wait_for_turn(turn: &AtomicU32, ticket: u32) {
+    loop {
+        let current = turn.load(Acquire);
+        if current == ticket {
+            return;
+        }
+        wait(turn, current);
+    }
+}
+
+pub struct FairReadGuard<'a, T> {
+    rwlock: &'a FairRwLock<T>,
+}
+
+impl<T> Deref for FairReadGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.rwlock.value.get() }
+    }
+}
+
+impl<T> Drop for FairReadGuard<'_, T> {
+    fn drop(&mut self) {
+        // The writer behind us has to wait for all readers of our batch.
+        self.rwlock.write_turn.fetch_add(1, Release);
+        wake_all(&self.rwlock.write_turn);
+    }
+}
+
+pub struct FairWriteGuard<'a, T> {
+    rwlock: &'a FairRwLock<T>,
+}
+
+impl<T> Deref for FairWriteGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.rwlock.value.get() }
+    }
+}
+
+impl<T> DerefMut for FairWriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.rwlock.value.get() }
+    }
+}
+
+impl<T> Drop for FairWriteGuard<'_, T> {
+    fn drop(&mut self) {
+        // Whoever holds the next ticket can go, reader or writer.
+        self.rwlock.read_turn.fetch_add(1, Release);
+        self.rwlock.write_turn.fetch_add(1, Release);
+        wake_all(&self.rwlock.read_turn);
+        wake_all(&self.rwlock.write_turn);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::FairRwLock;
+    use crate::primitives::mutex::Mutex;
+    use std::{sync::atomic::Ordering::Relaxed, thread};
+
+    #[test]
+    fn test() {
+        let rwlock = FairRwLock::new(0);
+        thread::scope(|s| {
+            for _ in 0..3 {
+                s.spawn(|| {
+                    for _ in 0..100 {
+                        *rwlock.write() += 1;
+                        assert!(*rwlock.read() <= 300);
+                    }
+                });
+            }
+        });
+        assert_eq!(*rwlock.read(), 300);
+    }
+
+    #[test]
+    fn test_arrival_order() {
+        // true for writers
+        let arrivals = [false, false, true, false, true, false, false];
+        // Consecutive readers may enter in any order, everything else in arrival order
+        let batches: [&[usize]; 5] = [&[0, 1], &[2], &[3], &[4], &[5, 6]];
+
+        let rwlock = FairRwLock::new(0);
+        let order = Mutex::new(Vec::new());
+
+        thread::scope(|s| {
+            let guard = rwlock.write();
+            for (i, &is_writer) in arrivals.iter().enumerate() {
+                let (rwlock, order) = (&rwlock, &order);
+                s.spawn(move || {
+                    if is_writer {
+                        let mut value = rwlock.write();
+                        order.lock().push(i);
+                        *value += 1;
+                    } else {
+                        let _value = rwlock.read();
+                        order.lock().push(i);
+                    }
+                });
+                // Wait until the thread took its ticket before the next one arrives.
+                while rwlock.next_ticket.load(Relaxed) != i as u32 + 2 {
+                    thread::yield_now();
+                }
+            }
+            drop(guard);
+        });
+
+        let order = order.lock();
+        let mut pos = 0;
+        for batch in batches {
+            let mut entered = order[pos..pos + batch.len()].to_vec();
+            entered.sort();
+            assert_eq!(entered, batch);
+            pos += batch.len();
+        }
+    }
+}
@@ -0,0 +1,36 @@
+use std::{sync::atomic::AtomicU32, time::Duration};
+
+/// Like `atomic_wait::wait`, but gives up after `timeout`.
+/// Can return early, both spuriously and because the value changed, so callers have to check.
+#[cfg(target_os = "linux")]
+pub(crate) fn wait_timeout(atomic: &AtomicU32, value: u32, timeout: Duration) {
+    let timeout = libc::timespec {
+        tv_sec: timeout.as_secs().min(libc::time_t::MAX as u64) as libc::time_t,
+        tv_nsec: timeout.subsec_nanos() as _,
+    };
+    // Same flags as `atomic_wait`, so its `wake_*` functions wake us up.
+    unsafe {
+        libc::syscall(
+            libc::SYS_futex,
+            atomic,
+            libc::FUTEX_WAIT | libc::FUTEX_PRIVATE_FLAG,
+            value,
+            &timeout,
+        );
+    }
+}
+
+/// Without a futex there's nothing to sleep on, so only sleep in small steps.
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn wait_timeout(atomic: &AtomicU32, value: u32, timeout: Duration) {
+    use std::{sync::atomic::Ordering::Relaxed, thread, time::Instant};
+
+    let deadline = Instant::now() + timeout;
+    while atomic.load(Relaxed) == value {
+        let now = Instant::now();
+        if now >= deadline {
+            return;
+        }
+        thread::sleep((deadline - now).min(Duration::from_millis(1)));
+    }
+}
@@ -0,0 +1,95 @@
+use std::{collections::HashMap, hash::Hash};
+
+use super::{
+    arc::{Arc, Weak},
+    mutex::Mutex,
+};
+
+/// Hands out the same `Arc` for equal values, as long as one of them is still alive.
+/// Only `Weak`s are kept, so the interner itself doesn't keep values alive.
+pub struct Interner<T> {
+    entries: Mutex<Entries<T>>,
+}
+
+struct Entries<T> {
+    map: HashMap<T, Weak<T>>,
+    /// Dead entries are pruned once the map grows to this size, twice what was left
+    /// after the last prune, so pruning costs amortized O(1) per interned value.
+    prune_at: usize,
+}
+
+impl<T: Eq + Hash + Clone> Interner<T> {
+    pub fn new() -> Self {
+        Self {
+            entries: Mutex::new(Entries {
+                map: HashMap::new(),
+                prune_at: 0,
+            }),
+        }
+    }
+
+    pub fn intern(&self, value: T) -> Arc<T> {
+        let mut entries = self.entries.lock();
+        if let Some(arc) = entries.map.get(&value).and_then(Weak::upgrade) {
+            return arc;
+        }
+        // Only prune when adding, so the map can't grow with dead entries only.
+        // `is_expired` doesn't upgrade, so no value gets dropped while the lock is held.
+        if entries.map.len() >= entries.prune_at {
+            entries.map.retain(|_, weak| !weak.is_expired());
+            entries.prune_at = 2 * entries.map.len();
+        }
+        let arc = Arc::new(value.clone());
+        entries.map.insert(value, arc.downgrade());
+        arc
+    }
+}
+
+impl<T: Eq + Hash + Clone> Default for Interner<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Interner;
+    use crate::primitives::arc::Arc;
+    use std::thread;
+
+    #[test]
+    fn test() {
+        let interner = Interner::new();
+        let a = interner.intern(String::from("a"));
+        let b = interner.intern(String::from("b"));
+        thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    assert!(Arc::ptr_eq(&interner.intern(String::from("a")), &a));
+                });
+            }
+        });
+        assert!(!Arc::ptr_eq(&a, &b));
+
+        drop(a);
+        // "a" died, so interning "c" prunes it.
+        let _c = interner.intern(String::from("c"));
+        assert_eq!(interner.entries.lock().map.len(), 2);
+
+        let a = interner.intern(String::from("a"));
+        assert_eq!(*a, "a");
+        assert!(Arc::ptr_eq(&interner.intern(String::from("a")), &a));
+    }
+
+    #[test]
+    fn test_prune_amortized() {
+        let interner = Interner::new();
+        let live: Vec<_> = (0..2).map(|i| interner.intern(i)).collect();
+        for i in 2..1000 {
+            drop(interner.intern(i));
+            // Never more than twice as many entries as live values.
+            assert!(interner.entries.lock().map.len() <= 2 * live.len());
+        }
+        assert!(Arc::ptr_eq(&interner.intern(0), &live[0]));
+    }
+}
@@ -0,0 +1,145 @@
+#![cfg(feature = "lock-tracing")]
+//! Reports every lock and unlock of a `Mutex` or `RwLock` to a global hook,
+//! e.g. for logging lock timelines while hunting down a deadlock.
+
+use std::{
+    mem, process, ptr,
+    sync::atomic::{
+        AtomicPtr,
+        Ordering::{Acquire, Release},
+    },
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockOp {
+    Lock,
+    Unlock,
+    Read,
+    ReadUnlock,
+    Write,
+    WriteUnlock,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LockEvent {
+    pub op: LockOp,
+    /// Address of the lock, to tell the locks apart.
+    pub addr: usize,
+    /// See `Mutex::with_name` and `RwLock::with_name`.
+    pub name: Option<&'static str>,
+}
+
+/// A `fn(&LockEvent)`, or null if there's no hook.
+static HOOK: AtomicPtr<()> = AtomicPtr::new(ptr::null_mut());
+
+/// Acquisitions are reported right after they happen, releases right before,
+/// so a lock's events never overlap. The hook runs on the locking thread, and must not
+/// use this crate's locks itself.
+///
+/// If the hook panics while reporting an acquisition, the panic unwinds out of the locking
+/// call with the lock released again, and poisons it like any other panic while holding it.
+/// A release is reported from a guard's `Drop`, where there's no way to get out with the
+/// lock still consistent, so a panic there aborts the process.
+pub fn set_lock_trace_hook(hook: Option<fn(&LockEvent)>) {
+    HOOK.store(
+        hook.map_or(ptr::null_mut(), |hook| hook as *mut ()),
+        Release,
+    );
+}
+
+pub(crate) fn trace<L>(op: LockOp, lock: &L, name: Option<&'static str>) {
+    let hook = HOOK.load(Acquire);
+    if hook.is_null() {
+        return;
+    }
+    // Safety: only ever set from a `fn(&LockEvent)`.
+    let hook = unsafe { mem::transmute::<*mut (), fn(&LockEvent)>(hook) };
+    let event = LockEvent {
+        op,
+        addr: lock as *const L as usize,
+        name,
+    };
+    match op {
+        LockOp::Unlock | LockOp::ReadUnlock | LockOp::WriteUnlock => {
+            let bomb = AbortOnUnwind;
+            hook(&event);
+            mem::forget(bomb);
+        }
+        _ => hook(&event),
+    }
+}
+
+/// Only dropped if the hook unwinds.
+struct AbortOnUnwind;
+
+impl Drop for AbortOnUnwind {
+    fn drop(&mut self) {
+        eprintln!("lock trace hook panicked while reporting a release, aborting");
+        process::abort();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{set_lock_trace_hook, LockEvent, LockOp::*};
+    use crate::primitives::{mutex::Mutex, rwlock::RwLock};
+    use std::{
+        panic::{self, AssertUnwindSafe},
+        thread,
+    };
+
+    // Not this crate's `Mutex`, that would report to the hook again.
+    static EVENTS: std::sync::Mutex<Vec<LockEvent>> = std::sync::Mutex::new(Vec::new());
+
+    #[test]
+    fn test() {
+        set_lock_trace_hook(Some(|event| {
+            EVENTS.lock().unwrap().push(*event);
+            if event.name == Some("panics") && event.op != Unlock {
+                panic!("hook");
+            }
+        }));
+        let mutex = Mutex::with_name(0, "counter");
+        thread::scope(|s| {
+            for _ in 0..2 {
+                s.spawn(|| {
+                    for _ in 0..3 {
+                        *mutex.lock() += 1;
+                    }
+                });
+            }
+        });
+        let rwlock = RwLock::new(0);
+        {
+            let guard = rwlock.read();
+            drop(guard.clone());
+        }
+        *rwlock.write() += 1;
+        // The lock is released again, only poisoned.
+        let panics = Mutex::with_name(0, "panics");
+        assert!(panic::catch_unwind(AssertUnwindSafe(|| drop(panics.lock()))).is_err());
+        assert!(panics.is_poisoned());
+        assert!(panic::catch_unwind(AssertUnwindSafe(|| drop(panics.lock()))).is_err());
+        set_lock_trace_hook(None);
+        drop(rwlock.read());
+
+        // Other tests lock concurrently, only look at ours.
+        let events = EVENTS.lock().unwrap();
+        let of = |addr: usize| {
+            events
+                .iter()
+                .filter(move |event| event.addr == addr)
+                .map(|event| (event.op, event.name))
+        };
+        let mutex_events: Vec<_> = of(&mutex as *const _ as usize).collect();
+        assert_eq!(mutex_events.len(), 12);
+        for pair in mutex_events.chunks(2) {
+            assert_eq!(pair, [(Lock, Some("counter")), (Unlock, Some("counter"))]);
+        }
+        let rwlock_ops: Vec<_> = of(&rwlock as *const _ as usize).map(|(op, _)| op).collect();
+        assert_eq!(
+            rwlock_ops,
+            [Read, Read, ReadUnlock, ReadUnlock, Write, WriteUnlock]
+        );
+    }
+}
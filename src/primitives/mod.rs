@@ -1,6 +1,27 @@
 pub mod arc;
+pub mod async_mutex;
+mod cache_padded;
+pub mod collector;
 pub mod condvar;
+pub mod event;
+pub mod fair_rwlock;
+mod futex;
+pub mod interner;
+pub mod lock_trace;
+pub mod monitor;
 pub mod mutex;
+pub mod notify;
+pub mod once_arc;
 pub mod oneshot_channel;
+pub mod persistent_stack;
+pub mod poison;
+pub mod rate_limiter;
+pub mod rc;
 pub mod rwlock;
+pub mod semaphore;
+pub mod sharded_rwlock;
+pub mod shared_cow;
+pub mod shared_join;
+pub mod signal_cell;
 pub mod spin_lock;
+pub mod spin_once;
@@ -0,0 +1,144 @@
+use std::ops::{Deref, DerefMut};
+
+use super::{
+    condvar::Condvar,
+    mutex::{Mutex, MutexGuard},
+};
+
+/// A `Mutex` bundled with the `Condvar` used to wait for changes to its value.
+pub struct Monitor<T> {
+    mutex: Mutex<T>,
+    condvar: Condvar,
+}
+
+impl<T> Monitor<T> {
+    pub const fn new(data: T) -> Self {
+        Self {
+            mutex: Mutex::new(data),
+            condvar: Condvar::new(),
+        }
+    }
+
+    pub fn lock(&self) -> MonitorGuard<'_, T> {
+        MonitorGuard {
+            guard: Some(self.mutex.lock()),
+            condvar: &self.condvar,
+        }
+    }
+
+    /// Locks and keeps waiting for notifications as long as `cond` returns true.
+    pub fn lock_and_wait_while(&self, mut cond: impl FnMut(&T) -> bool) -> MonitorGuard<'_, T> {
+        let mut guard = self.mutex.lock();
+        while cond(&guard) {
+            guard = self.condvar.wait(guard);
+        }
+        MonitorGuard {
+            guard: Some(guard),
+            condvar: &self.condvar,
+        }
+    }
+
+    pub fn notify_one(&self) {
+        self.condvar.notify_one();
+    }
+
+    pub fn notify_all(&self) {
+        self.condvar.notify_all();
+    }
+}
+
+/// Knows its condvar, so waiting can't pick the wrong one.
+/// Guards of a plain `Mutex` can't wait on a monitor's condvar either:
+/// ```compile_fail
+/// # use rust_concurrency::primitives::{monitor::MonitorGuard, mutex::Mutex};
+/// let mutex = Mutex::new(0);
+/// let mut guard = mutex.lock();
+/// MonitorGuard::wait(&mut guard);
+/// ```
+pub struct MonitorGuard<'a, T> {
+    /// Only `None` while waiting, or if `Condvar::wait` panicked.
+    guard: Option<MutexGuard<'a, T>>,
+    condvar: &'a Condvar,
+}
+
+impl<T> MonitorGuard<'_, T> {
+    /// Unlocks, waits for a notification and locks again. Can wake up spuriously.
+    pub fn wait(&mut self) {
+        let guard = self
+            .guard
+            .take()
+            .expect("monitor guard lost in a panicking wait");
+        self.guard = Some(self.condvar.wait(guard));
+    }
+}
+
+impl<T> Deref for MonitorGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        self.guard
+            .as_ref()
+            .expect("monitor guard lost in a panicking wait")
+    }
+}
+
+impl<T> DerefMut for MonitorGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.guard
+            .as_mut()
+            .expect("monitor guard lost in a panicking wait")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Monitor;
+    use std::{collections::VecDeque, thread};
+
+    #[test]
+    fn test() {
+        let queue = Monitor::new(VecDeque::new());
+        let received = thread::scope(|s| {
+            s.spawn(|| {
+                for i in 0..100 {
+                    queue.lock().push_back(i);
+                    queue.notify_one();
+                }
+            });
+
+            (0..100)
+                .map(|_| {
+                    queue
+                        .lock_and_wait_while(VecDeque::is_empty)
+                        .pop_front()
+                        .unwrap()
+                })
+                .collect::<Vec<_>>()
+        });
+        assert_eq!(received, (0..100).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_wait() {
+        let queue = Monitor::new(VecDeque::new());
+        let received = thread::scope(|s| {
+            s.spawn(|| {
+                for i in 0..100 {
+                    queue.lock().push_back(i);
+                    queue.notify_one();
+                }
+            });
+
+            let mut received = Vec::new();
+            let mut guard = queue.lock();
+            while received.len() < 100 {
+                match guard.pop_front() {
+                    Some(i) => received.push(i),
+                    None => guard.wait(),
+                }
+            }
+            received
+        });
+        assert_eq!(received, (0..100).collect::<Vec<_>>());
+    }
+}
@@ -1,16 +1,39 @@
 use std::{
     cell::UnsafeCell,
+    fmt, mem,
     ops::{Deref, DerefMut},
-    sync::atomic::{AtomicU32, AtomicU8, Ordering::*},
+    sync::atomic::{AtomicU32, Ordering::*},
+    thread,
+    time::{Duration, Instant},
 };
 
 use atomic_wait::{wait, wake_one};
+#[cfg(feature = "mutex-stats")]
+use std::sync::atomic::AtomicU64;
+
+#[cfg(feature = "lock-tracing")]
+use super::lock_trace::{trace, LockOp};
+use super::{
+    arc::Arc,
+    cache_padded::CachePadded,
+    condvar::Condvar,
+    futex::wait_timeout,
+    poison::{self, LockResult, PoisonError, TryLockError, TryLockResult},
+    rwlock::RwLock,
+};
 
 pub struct Mutex<T> {
     // 0: unlocked
     // 1: locked, no waiting threads
     // 2: locked, some waiting threads
-    state: AtomicU32,
+    state: CachePadded<AtomicU32>,
+    /// How long to spin before going to sleep when the lock is taken.
+    spin_limit: u32,
+    poison: poison::Flag,
+    /// Shown in `Debug` output.
+    name: Option<&'static str>,
+    #[cfg(feature = "mutex-stats")]
+    stats: Counters,
     data: UnsafeCell<T>,
 }
 
@@ -18,42 +41,391 @@ unsafe impl<T> Sync for Mutex<T> where T: Send {}
 
 impl<T> Mutex<T> {
     pub const fn new(data: T) -> Self {
-        return Mutex {
-            state: AtomicU32::new(0),
+        Mutex {
+            state: CachePadded::new(AtomicU32::new(0)),
+            spin_limit: 100,
+            poison: poison::Flag::new(),
+            name: None,
+            #[cfg(feature = "mutex-stats")]
+            stats: Counters::new(),
             data: UnsafeCell::new(data),
+        }
+    }
+
+    /// Waits on the futex right away instead of spinning first.
+    /// Spinning only pays off if the holder runs on another core and releases the lock soon,
+    /// so prefer this on single-core or power-sensitive targets,
+    /// or when the lock is usually held for a long time.
+    pub const fn new_no_spin(data: T) -> Self {
+        Mutex {
+            state: CachePadded::new(AtomicU32::new(0)),
+            spin_limit: 0,
+            poison: poison::Flag::new(),
+            name: None,
+            #[cfg(feature = "mutex-stats")]
+            stats: Counters::new(),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    /// `N` independent locks, e.g. for striping a sharded structure.
+    /// `init` gets each lock's index.
+    pub fn new_array<const N: usize>(mut init: impl FnMut(usize) -> T) -> [Self; N] {
+        std::array::from_fn(|i| Self::new(init(i)))
+    }
+
+    /// Labels the mutex for debugging, see `Debug`.
+    pub const fn with_name(data: T, name: &'static str) -> Self {
+        let mut mutex = Self::new(data);
+        mutex.name = Some(name);
+        mutex
+    }
+
+    pub fn name(&self) -> Option<&'static str> {
+        self.name
+    }
+
+    /// Doesn't report poisoning, see `is_poisoned` and `try_lock`.
+    pub fn lock(&self) -> MutexGuard<'_, T> {
+        self.acquire(None);
+        let guard = MutexGuard {
+            mutex: self,
+            poison: self.poison.guard(),
         };
+        #[cfg(feature = "lock-tracing")]
+        trace(LockOp::Lock, self, self.name);
+        guard
     }
 
-    pub fn lock(&self) -> MutexGuard<T> {
+    /// Like `lock`, but gives up after `timeout`. Also returns how long it took to get the lock,
+    /// e.g. for backing off when it's contended.
+    pub fn lock_timed(&self, timeout: Duration) -> Option<(MutexGuard<'_, T>, Duration)> {
+        let start = Instant::now();
+        if !self.acquire(Some(start + timeout)) {
+            return None;
+        }
+        let guard = MutexGuard {
+            mutex: self,
+            poison: self.poison.guard(),
+        };
+        #[cfg(feature = "lock-tracing")]
+        trace(LockOp::Lock, self, self.name);
+        Some((guard, start.elapsed()))
+    }
+
+    pub fn try_lock(&self) -> TryLockResult<MutexGuard<'_, T>> {
         if self.state.compare_exchange(0, 1, Acquire, Relaxed).is_err() {
-            lock_contended(&self.state);
+            return Err(TryLockError::WouldBlock);
+        }
+        let guard = MutexGuard {
+            mutex: self,
+            poison: self.poison.guard(),
+        };
+        #[cfg(feature = "lock-tracing")]
+        trace(LockOp::Lock, self, self.name);
+        if self.poison.get() {
+            return Err(TryLockError::Poisoned(PoisonError::new(guard)));
+        }
+        Ok(guard)
+    }
+
+    /// Runs `f` with the lock held if it's free, `None` if it isn't.
+    /// Like `lock`, this doesn't report poisoning.
+    pub fn try_with<R>(&self, f: impl FnOnce(&mut T) -> R) -> Option<R> {
+        let mut guard = match self.try_lock() {
+            Ok(guard) => guard,
+            Err(TryLockError::Poisoned(err)) => err.into_inner(),
+            Err(TryLockError::WouldBlock) => return None,
+        };
+        Some(f(&mut guard))
+    }
+
+    /// Locks and clears the poison, for when the data is known to be fine anyway.
+    pub fn lock_or_recover(&self) -> MutexGuard<'_, T> {
+        let guard = self.lock();
+        self.poison.clear();
+        guard
+    }
+
+    /// Whether a thread panicked while holding the lock.
+    pub fn is_poisoned(&self) -> bool {
+        self.poison.get()
+    }
+
+    pub fn clear_poison(&self) {
+        self.poison.clear();
+    }
+
+    /// No locking needed, the `&mut` already proves nobody else has access.
+    /// Like `try_lock`, reports poisoning, the error still hands out the data.
+    ///
+    /// There's no `from_mut(&mut T) -> &Mutex<T>` counterpart: the lock state is stored
+    /// next to the data, so a plain `T` doesn't have the layout of a `Mutex<T>`.
+    pub fn get_mut(&mut self) -> LockResult<&mut T> {
+        let poisoned = self.poison.get();
+        let data = self.data.get_mut();
+        if poisoned {
+            return Err(PoisonError::new(data));
+        }
+        Ok(data)
+    }
+
+    /// Reports poisoning like `get_mut`.
+    pub fn into_inner(self) -> LockResult<T> {
+        let poisoned = self.poison.get();
+        let data = self.data.into_inner();
+        if poisoned {
+            return Err(PoisonError::new(data));
+        }
+        Ok(data)
+    }
+
+    /// Keeps the value, the name and the poisoning, the rest is `RwLock::new`'s defaults.
+    pub fn into_rwlock(self) -> RwLock<T> {
+        let name = self.name;
+        let (data, poisoned) = match self.into_inner() {
+            Ok(data) => (data, false),
+            Err(err) => (err.into_inner(), true),
+        };
+        let rwlock = match name {
+            Some(name) => RwLock::with_name(data, name),
+            None => RwLock::new(data),
+        };
+        if poisoned {
+            rwlock.poison();
+        }
+        rwlock
+    }
+
+    pub(crate) fn poison(&self) {
+        self.poison.set();
+    }
+
+    /// Raw pointer to the protected data, for building custom guards.
+    /// Accessing it is only sound while holding the lock.
+    pub fn data_ptr(&self) -> *mut T {
+        self.data.get()
+    }
+
+    /// Locks once `cond` holds. First checks it in a loop, releasing the lock in between,
+    /// up to the spin limit of `new`, then falls back to waiting on `condvar`.
+    /// Worth it when the condition usually becomes true right away, so nobody has to sleep.
+    pub fn lock_and_spin_until(
+        &self,
+        condvar: &Condvar,
+        cond: impl Fn(&T) -> bool,
+    ) -> MutexGuard<'_, T> {
+        for _ in 0..self.spin_limit {
+            let guard = self.lock();
+            if cond(&guard) {
+                return guard;
+            }
+            drop(guard);
+            std::hint::spin_loop();
+        }
+        let mut guard = self.lock();
+        while !cond(&guard) {
+            guard = condvar.wait(guard);
+        }
+        guard
+    }
+
+    /// Like `lock`, but the guard holds its own `Arc`, so it can outlive `this`,
+    /// e.g. to be returned from a function.
+    pub fn lock_arc(this: &Arc<Mutex<T>>) -> ArcMutexGuard<T> {
+        let guard = this.lock();
+        let poison = guard.poison;
+        mem::forget(guard);
+        ArcMutexGuard {
+            mutex: this.clone(),
+            poison,
+        }
+    }
+
+    /// How `lock`, `lock_timed` and `MutexGuard::bump` got the lock so far, for tuning the spin
+    /// limit. `try_lock` isn't counted, and neither are `lock_timed` calls that gave up.
+    /// Taken with `Relaxed` loads, so they're only consistent with each other once the mutex
+    /// is idle.
+    #[cfg(feature = "mutex-stats")]
+    pub fn contention_stats(&self) -> MutexStats {
+        MutexStats {
+            fast_path: self.stats.fast_path.load(Relaxed),
+            contended: self.stats.contended.load(Relaxed),
+            spins: self.stats.spins.load(Relaxed),
+            futex_waits: self.stats.futex_waits.load(Relaxed),
+        }
+    }
+
+    /// Takes the lock, giving up at `deadline` if there is one. Returns whether it got it.
+    fn acquire(&self, deadline: Option<Instant>) -> bool {
+        if self.state.compare_exchange(0, 1, Acquire, Relaxed).is_ok() {
+            #[cfg(feature = "mutex-stats")]
+            self.stats.fast_path.fetch_add(1, Relaxed);
+            return true;
+        }
+        let locked = self.lock_contended(deadline);
+        #[cfg(feature = "mutex-stats")]
+        if locked {
+            self.stats.contended.fetch_add(1, Relaxed);
+        }
+        locked
+    }
+
+    fn lock_contended(&self, deadline: Option<Instant>) -> bool {
+        let state = &*self.state;
+        let mut spin_count = 0;
+        while state.load(Relaxed) == 1 && spin_count < self.spin_limit {
+            spin_count += 1;
+            std::hint::spin_loop();
+        }
+        #[cfg(feature = "mutex-stats")]
+        self.stats.spins.fetch_add(spin_count.into(), Relaxed);
+
+        if state.compare_exchange(0, 1, Acquire, Relaxed).is_ok() {
+            return true;
+        }
+
+        // Giving up leaves the state at 2, which only costs the holder an extra wake call.
+        while state.swap(2, Acquire) != 0 {
+            match deadline {
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return false;
+                    }
+                    #[cfg(feature = "mutex-stats")]
+                    self.stats.futex_waits.fetch_add(1, Relaxed);
+                    wait_timeout(state, 2, deadline - now);
+                }
+                None => {
+                    #[cfg(feature = "mutex-stats")]
+                    self.stats.futex_waits.fetch_add(1, Relaxed);
+                    wait(state, 2);
+                }
+            }
+        }
+        true
+    }
+
+    fn unlock(&self, poison: &poison::Guard) {
+        #[cfg(feature = "lock-tracing")]
+        trace(LockOp::Unlock, self, self.name);
+        self.poison.done(poison);
+        if self.state.swap(0, Release) == 2 {
+            wake_one(&*self.state);
         }
-        MutexGuard { mutex: self }
     }
 }
 
-fn lock_contended(state: &AtomicU32) {
-    let mut spin_count = 0;
-    while state.load(Relaxed) == 1 && spin_count < 100 {
-        spin_count += 1;
-        std::hint::spin_loop();
+impl<T: Default> Mutex<T> {
+    /// Moves the value out, and stores what `f` turns it into.
+    /// If `f` panics, the value stays `T::default()`, and the mutex is poisoned.
+    pub fn replace_with(&self, f: impl FnOnce(T) -> T) {
+        let mut guard = self.lock();
+        let value = mem::take(&mut *guard);
+        *guard = f(value);
     }
+}
 
-    if state.compare_exchange(0, 1, Acquire, Relaxed).is_ok() {
-        return;
+impl<T: fmt::Debug> fmt::Debug for Mutex<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut d = f.debug_struct("Mutex");
+        if let Some(name) = self.name {
+            d.field("name", &name);
+        }
+        match self.try_lock() {
+            Ok(guard) => d.field("data", &&*guard),
+            Err(TryLockError::Poisoned(err)) => d.field("data", &&**err.get_ref()),
+            Err(TryLockError::WouldBlock) => d.field("data", &format_args!("<locked>")),
+        };
+        d.field("poisoned", &self.is_poisoned());
+        d.finish_non_exhaustive()
     }
+}
 
-    while state.swap(2, Acquire) != 0 {
-        wait(state, 2);
+/// See `Mutex::contention_stats`.
+#[cfg(feature = "mutex-stats")]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MutexStats {
+    /// Acquisitions that found the mutex unlocked.
+    pub fast_path: u64,
+    /// Acquisitions that had to spin or wait.
+    pub contended: u64,
+    /// Spin loop iterations, summed over all contended calls.
+    pub spins: u64,
+    /// Times a thread went to sleep on the futex.
+    pub futex_waits: u64,
+}
+
+#[cfg(feature = "mutex-stats")]
+struct Counters {
+    fast_path: AtomicU64,
+    contended: AtomicU64,
+    spins: AtomicU64,
+    futex_waits: AtomicU64,
+}
+
+#[cfg(feature = "mutex-stats")]
+impl Counters {
+    const fn new() -> Self {
+        Self {
+            fast_path: AtomicU64::new(0),
+            contended: AtomicU64::new(0),
+            spins: AtomicU64::new(0),
+            futex_waits: AtomicU64::new(0),
+        }
     }
 }
 
+/// Unlike `std::sync::MutexGuard`, this one may be sent to and unlocked on another thread,
+/// as long as `T: Send`:
+/// ```
+/// # use rust_concurrency::primitives::mutex::MutexGuard;
+/// fn assert_send<T: Send>() {}
+/// fn assert_sync<T: Sync>() {}
+/// assert_send::<MutexGuard<'static, std::cell::Cell<i32>>>();
+/// assert_sync::<MutexGuard<'static, i32>>();
+/// ```
+/// ```compile_fail
+/// # use rust_concurrency::primitives::mutex::MutexGuard;
+/// fn assert_send<T: Send>() {}
+/// assert_send::<MutexGuard<'static, std::rc::Rc<i32>>>();
+/// ```
+/// Sharing it hands out `&T`, so that requires `T: Sync`:
+/// ```compile_fail
+/// # use rust_concurrency::primitives::mutex::MutexGuard;
+/// fn assert_sync<T: Sync>() {}
+/// assert_sync::<MutexGuard<'static, std::cell::Cell<i32>>>();
+/// ```
 pub struct MutexGuard<'a, T> {
     pub mutex: &'a Mutex<T>,
+    poison: poison::Guard,
 }
 
 unsafe impl<T> Sync for MutexGuard<'_, T> where T: Sync {}
 
+impl<'a, T> MutexGuard<'a, T> {
+    /// Gives up the guard without unlocking, so the mutex stays locked forever
+    /// and the returned reference is the only way left to reach the data.
+    pub fn leak(self) -> &'a mut T {
+        let data = self.mutex.data.get();
+        mem::forget(self);
+        unsafe { &mut *data }
+    }
+
+    /// Unlocks and locks again, yielding in between so a waiting thread can get in first.
+    /// For long critical sections that have points where the data is consistent anyway.
+    pub fn bump(&mut self) {
+        self.mutex.unlock(&self.poison);
+        thread::yield_now();
+        let mutex = self.mutex;
+        mutex.acquire(None);
+        self.poison = mutex.poison.guard();
+        #[cfg(feature = "lock-tracing")]
+        trace(LockOp::Lock, mutex, mutex.name);
+    }
+}
+
 impl<T> Deref for MutexGuard<'_, T> {
     type Target = T;
 
@@ -70,16 +442,53 @@ impl<T> DerefMut for MutexGuard<'_, T> {
 
 impl<T> Drop for MutexGuard<'_, T> {
     fn drop(&mut self) {
-        if self.mutex.state.swap(0, Release) == 2 {
-            wake_one(&self.mutex.state);
-        }
+        self.mutex.unlock(&self.poison);
+    }
+}
+
+/// Returned by `Mutex::lock_arc`, the same as `MutexGuard` otherwise.
+pub struct ArcMutexGuard<T> {
+    mutex: Arc<Mutex<T>>,
+    poison: poison::Guard,
+}
+
+// Like for `MutexGuard`, sharing the guard shares `&T`.
+unsafe impl<T> Sync for ArcMutexGuard<T> where T: Send + Sync {}
+
+impl<T> ArcMutexGuard<T> {
+    pub fn mutex(this: &ArcMutexGuard<T>) -> &Arc<Mutex<T>> {
+        &this.mutex
+    }
+}
+
+impl<T> Deref for ArcMutexGuard<T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.mutex.data.get() }
+    }
+}
+
+impl<T> DerefMut for ArcMutexGuard<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.mutex.data.get() }
+    }
+}
+
+impl<T> Drop for ArcMutexGuard<T> {
+    fn drop(&mut self) {
+        self.mutex.unlock(&self.poison);
     }
 }
 
 #[cfg(test)]
 mod test {
-    use super::Mutex;
-    use std::thread;
+    use super::{Arc, ArcMutexGuard, Condvar, Mutex, TryLockError};
+    use std::{
+        sync::atomic::{AtomicUsize, Ordering::Relaxed},
+        thread,
+        time::Duration,
+    };
 
     #[test]
     fn test() {
@@ -95,4 +504,349 @@ mod test {
         let g = mutex.lock();
         assert!(*g == vec![1, 2, 3] || *g == vec![2, 3, 1]);
     }
+
+    #[test]
+    fn test_no_spin() {
+        let mutex = Mutex::new_no_spin(0);
+        thread::scope(|s| {
+            let g = mutex.lock();
+            for _ in 0..4 {
+                s.spawn(|| {
+                    for _ in 0..100 {
+                        let mut g = mutex.lock();
+                        let val = *g;
+                        thread::yield_now();
+                        *g = val + 1;
+                    }
+                });
+            }
+            // Someone went to sleep without spinning, and will be woken up.
+            while mutex.state.load(Relaxed) != 2 {
+                thread::yield_now();
+            }
+            drop(g);
+        });
+        assert_eq!(*mutex.lock(), 400);
+    }
+
+    #[test]
+    fn test_get_mut() {
+        let mut mutex = Mutex::new(vec![1]);
+        mutex.get_mut().unwrap().push(2);
+        assert_eq!(*mutex.lock(), [1, 2]);
+
+        thread::scope(|s| {
+            let panicked = s.spawn(|| {
+                let _g = mutex.lock();
+                panic!("poisoning the mutex");
+            });
+            assert!(panicked.join().is_err());
+        });
+        // Still gives access, the caller decides whether the data is fine.
+        mutex.get_mut().unwrap_err().into_inner().push(3);
+        assert!(mutex.get_mut().is_err());
+        mutex.clear_poison();
+        mutex.get_mut().unwrap().push(4);
+        assert_eq!(*mutex.try_lock().unwrap(), [1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn test_into_rwlock() {
+        let mutex = Mutex::new(String::from("a"));
+        mutex.lock().push('b');
+        let rwlock = mutex.into_rwlock();
+        assert_eq!(rwlock.name(), None);
+        assert_eq!(*rwlock.read(), "ab");
+        assert_eq!(rwlock.into_mutex().into_inner().unwrap(), "ab");
+
+        let mutex = Mutex::with_name(1, "poisoned");
+        thread::scope(|s| {
+            let panicked = s.spawn(|| {
+                let _g = mutex.lock();
+                panic!("poisoning the mutex");
+            });
+            assert!(panicked.join().is_err());
+        });
+        let rwlock = mutex.into_rwlock();
+        assert_eq!(rwlock.name(), Some("poisoned"));
+        assert!(rwlock.is_poisoned());
+        assert_eq!(rwlock.into_inner().unwrap_err().into_inner(), 1);
+    }
+
+    #[test]
+    fn test_try_with() {
+        let mutex = Mutex::new(1);
+        assert_eq!(mutex.try_with(|v| *v + 1), Some(2));
+        let g = mutex.lock();
+        assert_eq!(mutex.try_with(|_| unreachable!()), None::<()>);
+        drop(g);
+        mutex.try_with(|v| *v = 3);
+        assert_eq!(*mutex.lock(), 3);
+    }
+
+    #[test]
+    fn test_debug() {
+        let mutex = Mutex::with_name(vec![1], "queue");
+        assert_eq!(mutex.name(), Some("queue"));
+        assert_eq!(
+            format!("{mutex:?}"),
+            r#"Mutex { name: "queue", data: [1], poisoned: false, .. }"#
+        );
+        let _g = mutex.lock();
+        assert_eq!(
+            format!("{mutex:?}"),
+            r#"Mutex { name: "queue", data: <locked>, poisoned: false, .. }"#
+        );
+        assert_eq!(
+            format!("{:?}", Mutex::new(1)),
+            "Mutex { data: 1, poisoned: false, .. }"
+        );
+    }
+
+    #[test]
+    fn test_try_lock() {
+        let mutex = Mutex::new(0);
+        {
+            let _g = mutex.lock();
+            assert!(matches!(mutex.try_lock(), Err(TryLockError::WouldBlock)));
+        }
+        *mutex.try_lock().unwrap() += 1;
+
+        thread::scope(|s| {
+            let panicked = s.spawn(|| {
+                let _g = mutex.lock();
+                panic!("poisoning the mutex");
+            });
+            assert!(panicked.join().is_err());
+        });
+        assert!(mutex.is_poisoned());
+        let result = mutex.try_lock();
+        match result {
+            Err(TryLockError::Poisoned(err)) => assert_eq!(*err.into_inner(), 1),
+            _ => panic!("expected a poisoned mutex"),
+        }
+    }
+
+    #[test]
+    fn test_recover_from_poison() {
+        let mutex = Mutex::new(0);
+        let poison = || {
+            thread::scope(|s| {
+                let panicked = s.spawn(|| {
+                    *mutex.lock() += 1;
+                    let _g = mutex.lock();
+                    panic!("poisoning the mutex");
+                });
+                assert!(panicked.join().is_err());
+            });
+            assert!(mutex.is_poisoned());
+        };
+
+        poison();
+        mutex.clear_poison();
+        assert!(!mutex.is_poisoned());
+        assert_eq!(*mutex.try_lock().unwrap(), 1);
+
+        poison();
+        *mutex.lock_or_recover() += 1;
+        assert!(!mutex.is_poisoned());
+        assert_eq!(*mutex.try_lock().unwrap(), 3);
+    }
+
+    #[test]
+    fn test_leak() {
+        static MUTEX: Mutex<Vec<i32>> = Mutex::new(Vec::new());
+        let data: &'static mut Vec<i32> = MUTEX.lock().leak();
+        data.push(1);
+        assert_eq!(*data, [1]);
+        assert!(matches!(MUTEX.try_lock(), Err(TryLockError::WouldBlock)));
+    }
+
+    #[test]
+    fn test_data_ptr() {
+        let mutex = Mutex::new(1);
+        let guard = mutex.lock();
+        unsafe {
+            *mutex.data_ptr() += 1;
+            assert_eq!(*mutex.data_ptr(), 2);
+        }
+        assert_eq!(*guard, 2);
+    }
+
+    #[test]
+    fn test_lock_arc() {
+        fn locked_queue() -> ArcMutexGuard<Vec<i32>> {
+            let queue = Arc::new(Mutex::new(vec![1]));
+            Mutex::lock_arc(&queue)
+        }
+
+        let mut guard = locked_queue();
+        guard.push(2);
+        let mutex = ArcMutexGuard::mutex(&guard).clone();
+        assert_eq!(Arc::strong_count(&mutex), 2);
+        assert!(mutex.try_lock().is_err());
+
+        thread::scope(|s| {
+            s.spawn(|| mutex.lock().push(3));
+            thread::yield_now();
+            drop(guard);
+        });
+        assert_eq!(*mutex.lock(), [1, 2, 3]);
+        assert_eq!(Arc::strong_count(&mutex), 1);
+    }
+
+    #[test]
+    fn test_lock_timed() {
+        let mutex = Mutex::new(0);
+        let (guard, waited) = mutex.lock_timed(Duration::from_secs(1)).unwrap();
+        assert!(waited < Duration::from_millis(100));
+        drop(guard);
+
+        thread::scope(|s| {
+            let g = mutex.lock();
+            s.spawn(move || {
+                thread::sleep(Duration::from_millis(200));
+                drop(g);
+            });
+            assert!(mutex.lock_timed(Duration::from_millis(50)).is_none());
+            let (mut guard, waited) = mutex.lock_timed(Duration::from_secs(5)).unwrap();
+            assert!(waited >= Duration::from_millis(100) && waited < Duration::from_secs(2));
+            *guard += 1;
+        });
+        assert_eq!(*mutex.lock(), 1);
+    }
+
+    #[test]
+    fn test_lock_and_spin_until() {
+        let mutex = Mutex::new(false);
+        let condvar = Condvar::new();
+
+        // Becomes true while spinning, nobody has to notify.
+        let checks = AtomicUsize::new(0);
+        let guard = mutex.lock_and_spin_until(&condvar, |_| checks.fetch_add(1, Relaxed) == 5);
+        drop(guard);
+        assert_eq!(checks.load(Relaxed), 6);
+        assert_eq!(condvar.waiter_count(), 0);
+
+        // Takes too long, so it ends up waiting for a notification.
+        thread::scope(|s| {
+            s.spawn(|| {
+                while condvar.waiter_count() == 0 {
+                    thread::yield_now();
+                }
+                *mutex.lock() = true;
+                condvar.notify_all();
+            });
+            assert!(*mutex.lock_and_spin_until(&condvar, |ready| *ready));
+        });
+    }
+
+    #[cfg(feature = "mutex-stats")]
+    #[test]
+    fn test_contention_stats() {
+        let mutex = Mutex::new(0);
+        for _ in 0..10 {
+            *mutex.lock() += 1;
+        }
+        let stats = mutex.contention_stats();
+        assert_eq!((stats.fast_path, stats.contended, stats.spins), (10, 0, 0));
+
+        thread::scope(|s| {
+            let guard = mutex.lock();
+            s.spawn(|| *mutex.lock() += 1);
+            // Keep it locked until the other thread gave up spinning and went to sleep.
+            while mutex.contention_stats().futex_waits == 0 {
+                thread::yield_now();
+            }
+            drop(guard);
+        });
+        let stats = mutex.contention_stats();
+        assert_eq!((stats.fast_path, stats.contended), (11, 1));
+        assert_eq!(stats.spins, 100);
+        assert!(stats.futex_waits >= 1);
+        assert_eq!(*mutex.lock(), 11);
+
+        // Every way of getting the lock counts once, giving up doesn't.
+        let mutex = Mutex::new(0);
+        let (mut guard, _) = mutex.lock_timed(Duration::from_secs(1)).unwrap();
+        guard.bump();
+        thread::scope(|s| {
+            s.spawn(|| assert!(mutex.lock_timed(Duration::from_millis(10)).is_none()));
+        });
+        drop(guard);
+        let stats = mutex.contention_stats();
+        assert_eq!((stats.fast_path, stats.contended), (2, 0));
+        assert!(stats.futex_waits >= 1);
+    }
+
+    #[test]
+    fn test_bump() {
+        let mutex = Mutex::new(Vec::new());
+        thread::scope(|s| {
+            let mut guard = mutex.lock();
+            s.spawn(|| mutex.lock().push("waiter"));
+            for _ in 0..1000 {
+                guard.push("holder");
+                if guard.contains(&"waiter") {
+                    break;
+                }
+                thread::sleep(Duration::from_millis(1));
+                guard.bump();
+            }
+            // The waiter got in while the guard was still around.
+            assert_eq!(guard.last(), Some(&"holder"));
+            assert!(guard.contains(&"waiter"));
+        });
+        assert!(!mutex.is_poisoned());
+    }
+
+    #[test]
+    fn test_new_array() {
+        let shards: [Mutex<u64>; 4] = Mutex::new_array(|_| 0);
+        thread::scope(|s| {
+            for t in 0..8 {
+                let shard = &shards[t % shards.len()];
+                s.spawn(move || {
+                    for _ in 0..100 {
+                        *shard.lock() += 1;
+                    }
+                });
+            }
+        });
+        assert!(shards.iter().all(|shard| *shard.lock() == 200));
+        assert_eq!(shards.iter().map(|shard| *shard.lock()).sum::<u64>(), 800);
+        let mut indices: [Mutex<usize>; 3] = Mutex::new_array(|i| i);
+        assert_eq!(indices.each_mut().map(|m| *m.get_mut().unwrap()), [0, 1, 2]);
+    }
+
+    #[test]
+    fn test_replace_with() {
+        let mutex = Mutex::new(vec![1, 2]);
+        mutex.replace_with(|v| v.into_iter().map(|i| i * 10).collect());
+        assert_eq!(*mutex.lock(), [10, 20]);
+
+        thread::scope(|s| {
+            let panicked = s.spawn(|| mutex.replace_with(|_| panic!("replacing failed")));
+            assert!(panicked.join().is_err());
+        });
+        assert!(mutex.is_poisoned());
+        assert!(mutex.lock().is_empty());
+    }
+
+    #[test]
+    fn test_const() {
+        // Keeps the constructors usable in statics, whatever fields get added.
+        static PLAIN: Mutex<i32> = Mutex::new(0);
+        static NO_SPIN: Mutex<i32> = Mutex::new_no_spin(1);
+        static NAMED: Mutex<i32> = Mutex::with_name(2, "named");
+        let fresh = [const { Mutex::new(3) }; 2];
+
+        thread::scope(|s| {
+            s.spawn(|| *PLAIN.lock() += 1);
+            s.spawn(|| *NO_SPIN.lock() += 1);
+        });
+        assert_eq!((*PLAIN.lock(), *NO_SPIN.lock()), (1, 2));
+        assert_eq!(NAMED.name(), Some("named"));
+        assert_eq!(*fresh[1].lock(), 3);
+    }
 }
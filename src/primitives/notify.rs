@@ -0,0 +1,190 @@
+use std::sync::atomic::{AtomicU32, Ordering::SeqCst};
+
+use atomic_wait::{wait, wake_all, wake_one};
+
+/// Wakes up threads waiting for something to happen, without a mutex, like `tokio::sync::Notify`.
+/// `notify_one` without a waiter leaves a permit for the next `notified`,
+/// `notify_waiters` only wakes the threads already waiting.
+pub struct Notify {
+    /// Lowest bit: a permit is stored. Bits 1 to 15 count the waiters, the upper 16 bits
+    /// count the wakeups handed out to them but not taken yet, never more than there are waiters.
+    state: AtomicU32,
+    /// Bumped by every wakeup, the waiters sleep on it.
+    seq: AtomicU32,
+    /// Counts `notify_waiters` calls.
+    generation: AtomicU32,
+}
+
+const PERMIT: u32 = 1;
+const WAITER: u32 = 2;
+const WAITERS: u32 = 0xfffe;
+const WAKEUP: u32 = 1 << 16;
+
+impl Notify {
+    pub const fn new() -> Self {
+        Self {
+            state: AtomicU32::new(0),
+            seq: AtomicU32::new(0),
+            generation: AtomicU32::new(0),
+        }
+    }
+
+    /// Blocks until notified, taking the permit if there is one.
+    pub fn notified(&self) {
+        // SeqCst throughout, so a notification either shows up in the state
+        // or bumps `seq` after we read it.
+        let generation = self.generation.load(SeqCst);
+        let mut state = self.state.load(SeqCst);
+        loop {
+            let new = if state & PERMIT != 0 {
+                state & !PERMIT
+            } else {
+                assert!(
+                    state & WAITERS != WAITERS,
+                    "too many threads waiting on Notify"
+                );
+                state + WAITER
+            };
+            match self.state.compare_exchange(state, new, SeqCst, SeqCst) {
+                Ok(_) if state & PERMIT != 0 => return,
+                Ok(_) => break,
+                Err(e) => state = e,
+            }
+        }
+
+        loop {
+            let seq = self.seq.load(SeqCst);
+            let mut state = self.state.load(SeqCst);
+            loop {
+                // Whichever waiter comes first takes the wakeup, the count is all that matters.
+                // Taking one is also how a waiter leaves after `notify_waiters`, if there is one.
+                let new = if state >= WAKEUP {
+                    state - WAKEUP - WAITER
+                } else if self.generation.load(SeqCst) != generation {
+                    state - WAITER
+                } else {
+                    break;
+                };
+                match self.state.compare_exchange(state, new, SeqCst, SeqCst) {
+                    Ok(_) => return,
+                    Err(e) => state = e,
+                }
+            }
+            wait(&self.seq, seq);
+        }
+    }
+
+    /// Wakes up one waiter, or stores a permit if every waiter is already being woken up.
+    /// Permits don't add up.
+    pub fn notify_one(&self) {
+        let mut state = self.state.load(SeqCst);
+        loop {
+            let waiters = (state & WAITERS) / WAITER;
+            let new = if state / WAKEUP < waiters {
+                state + WAKEUP
+            } else if state & PERMIT == 0 {
+                state | PERMIT
+            } else {
+                return;
+            };
+            match self.state.compare_exchange(state, new, SeqCst, SeqCst) {
+                Ok(_) if new & PERMIT == 0 => {
+                    self.seq.fetch_add(1, SeqCst);
+                    wake_one(&self.seq);
+                    return;
+                }
+                Ok(_) => return,
+                Err(e) => state = e,
+            }
+        }
+    }
+
+    /// Wakes up everyone currently waiting, without storing a permit.
+    pub fn notify_waiters(&self) {
+        self.generation.fetch_add(1, SeqCst);
+        self.seq.fetch_add(1, SeqCst);
+        wake_all(&self.seq);
+    }
+}
+
+impl Default for Notify {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Notify, PERMIT, WAITER};
+    use std::{
+        sync::atomic::{AtomicUsize, Ordering::Relaxed},
+        thread,
+        time::{Duration, Instant},
+    };
+
+    #[test]
+    fn test() {
+        // Notified before waiting, the permit is kept.
+        let notify = Notify::new();
+        notify.notify_one();
+        notify.notify_one();
+        notify.notified();
+        assert_eq!(notify.state.load(Relaxed) & PERMIT, 0);
+
+        // Waiting before being notified.
+        thread::scope(|s| {
+            let waiter = s.spawn(|| notify.notified());
+            thread::sleep(Duration::from_millis(50));
+            assert!(!waiter.is_finished());
+            notify.notify_one();
+        });
+    }
+
+    #[test]
+    fn test_notify_waiters() {
+        let notify = Notify::new();
+        let woken = AtomicUsize::new(0);
+        thread::scope(|s| {
+            for _ in 0..3 {
+                s.spawn(|| {
+                    notify.notified();
+                    woken.fetch_add(1, Relaxed);
+                });
+            }
+            thread::sleep(Duration::from_millis(50));
+            notify.notify_waiters();
+        });
+        assert_eq!(woken.load(Relaxed), 3);
+        // No permit left behind.
+        assert_eq!(notify.state.load(Relaxed) & PERMIT, 0);
+    }
+
+    #[test]
+    fn test_notify_one_back_to_back() {
+        let notify = Notify::new();
+        let woken = AtomicUsize::new(0);
+        thread::scope(|s| {
+            for _ in 0..2 {
+                s.spawn(|| {
+                    notify.notified();
+                    woken.fetch_add(1, Relaxed);
+                });
+            }
+            while notify.state.load(Relaxed) != 2 * WAITER {
+                thread::yield_now();
+            }
+            // The second one comes before the first woken waiter got to run.
+            notify.notify_one();
+            notify.notify_one();
+            let start = Instant::now();
+            while woken.load(Relaxed) < 2 {
+                assert!(
+                    start.elapsed() < Duration::from_secs(5),
+                    "a notification was lost"
+                );
+                thread::yield_now();
+            }
+        });
+        assert_eq!(notify.state.load(Relaxed), 0);
+    }
+}
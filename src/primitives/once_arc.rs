@@ -0,0 +1,146 @@
+use std::{
+    cell::UnsafeCell,
+    mem::{self, MaybeUninit},
+    sync::atomic::{
+        AtomicU32,
+        Ordering::{Acquire, Release},
+    },
+};
+
+use atomic_wait::{wait, wake_all};
+
+use super::arc::Arc;
+
+const EMPTY: u32 = 0;
+/// A thread is running the initializer, the others wait.
+const RUNNING: u32 = 1;
+const DONE: u32 = 2;
+
+/// Lazily creates a value shared by all threads, handing out `Arc`s to it.
+pub struct OnceArc<T> {
+    state: AtomicU32,
+    arc: UnsafeCell<MaybeUninit<Arc<T>>>,
+}
+
+unsafe impl<T> Sync for OnceArc<T> where T: Send + Sync {}
+
+impl<T> OnceArc<T> {
+    pub const fn new() -> Self {
+        Self {
+            state: AtomicU32::new(EMPTY),
+            arc: UnsafeCell::new(MaybeUninit::uninit()),
+        }
+    }
+
+    /// Runs `f` on the first call only, other threads calling this meanwhile wait for it.
+    /// If `f` panics, the next caller runs its own `f` instead.
+    pub fn get_or_init(&self, f: impl FnOnce() -> T) -> Arc<T> {
+        loop {
+            match self
+                .state
+                .compare_exchange(EMPTY, RUNNING, Acquire, Acquire)
+            {
+                Ok(_) => break,
+                Err(DONE) => return unsafe { self.get_unchecked() },
+                Err(_) => wait(&self.state, RUNNING),
+            }
+        }
+
+        let reset = ResetOnPanic(&self.state);
+        let arc = Arc::new(f());
+        mem::forget(reset);
+        unsafe { (*self.arc.get()).write(arc.clone()) };
+        self.state.store(DONE, Release);
+        wake_all(&self.state);
+        arc
+    }
+
+    pub fn get(&self) -> Option<Arc<T>> {
+        if self.state.load(Acquire) == DONE {
+            Some(unsafe { self.get_unchecked() })
+        } else {
+            None
+        }
+    }
+
+    /// Safety: the state must be `DONE`, observed with `Acquire`.
+    unsafe fn get_unchecked(&self) -> Arc<T> {
+        (*self.arc.get()).assume_init_ref().clone()
+    }
+}
+
+/// Lets the next caller try again, and wakes up the ones already waiting for that.
+struct ResetOnPanic<'a>(&'a AtomicU32);
+
+impl Drop for ResetOnPanic<'_> {
+    fn drop(&mut self) {
+        self.0.store(EMPTY, Release);
+        wake_all(self.0);
+    }
+}
+
+impl<T> Default for OnceArc<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Drop for OnceArc<T> {
+    fn drop(&mut self) {
+        if *self.state.get_mut() == DONE {
+            unsafe { self.arc.get_mut().assume_init_drop() }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Arc, OnceArc};
+    use std::{
+        panic::{catch_unwind, AssertUnwindSafe},
+        sync::atomic::{AtomicUsize, Ordering::Relaxed},
+        thread,
+        time::Duration,
+    };
+
+    #[test]
+    fn test() {
+        let once = OnceArc::new();
+        let calls = AtomicUsize::new(0);
+        assert!(once.get().is_none());
+
+        let arcs: Vec<_> = thread::scope(|s| {
+            let handles: Vec<_> = (0..8)
+                .map(|i| {
+                    let (once, calls) = (&once, &calls);
+                    s.spawn(move || {
+                        once.get_or_init(|| {
+                            calls.fetch_add(1, Relaxed);
+                            thread::sleep(Duration::from_millis(50));
+                            i
+                        })
+                    })
+                })
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        });
+
+        assert_eq!(calls.load(Relaxed), 1);
+        assert!(arcs.iter().all(|arc| Arc::ptr_eq(arc, &arcs[0])));
+        assert!(Arc::ptr_eq(&once.get().unwrap(), &arcs[0]));
+        // One for each thread, plus the one kept by `once`.
+        assert_eq!(Arc::strong_count(&arcs[0]), 9);
+    }
+
+    #[test]
+    fn test_panic() {
+        let once = OnceArc::new();
+        let result = catch_unwind(AssertUnwindSafe(|| {
+            once.get_or_init(|| panic!("init failed"))
+        }));
+        assert!(result.is_err());
+        assert!(once.get().is_none());
+        assert_eq!(*once.get_or_init(|| 1), 1);
+        assert_eq!(*once.get_or_init(|| 2), 1);
+    }
+}
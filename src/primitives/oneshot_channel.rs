@@ -1,76 +1,618 @@
 use std::{
     cell::UnsafeCell,
+    error::Error,
+    fmt,
     marker::PhantomData,
-    mem::MaybeUninit,
+    mem::{self, MaybeUninit},
+    ptr::{self, NonNull},
     sync::atomic::{
-        AtomicBool,
-        Ordering::{Acquire, Release},
+        AtomicPtr, AtomicU32, AtomicU64, AtomicU8, AtomicUsize,
+        Ordering::{AcqRel, Acquire, Relaxed, Release},
     },
     thread::{self, Thread},
 };
 
+use atomic_wait::{wait, wake_one};
+
+/// Nothing sent yet, or the message was already taken.
+const EMPTY: u8 = 0;
+/// The message is waiting for the receiver.
+const READY: u8 = 1;
+/// The receiver registered a callback for the sender to run.
+const CALLBACK: u8 = 2;
+/// The receiver registered its thread and is parked until the message arrives.
+const WAITING: u8 = 3;
+/// The sender was dropped without sending.
+const DISCONNECTED: u8 = 4;
+
+/// Whether the message was received, for `Sender::send_and_confirm`.
+const NOT_RECEIVED: u32 = 0;
+/// The sender is blocked until the message is received.
+const CONFIRM_WAITING: u32 = 1;
+const RECEIVED: u32 = 2;
+/// The receiver was dropped without taking the message.
+const RECEIVER_GONE: u32 = 3;
+
+/// What the receiver left behind for the sender.
+enum Waiter<T> {
+    Thread(Thread),
+    Callback(Box<dyn FnOnce(T) + Send>),
+}
+
 pub struct OneshotChannel<T> {
     message: UnsafeCell<MaybeUninit<T>>,
-    ready: AtomicBool,
+    waiter: UnsafeCell<Option<Waiter<T>>>,
+    state: AtomicU8,
+    received: AtomicU32,
 }
 
 unsafe impl<T> Sync for OneshotChannel<T> where T: Send {}
 
 pub struct Sender<'a, T> {
     channel: &'a OneshotChannel<T>,
-    receiving_thread: Thread,
 }
 
+/// Can be moved to another thread, the thread to wake up is only registered by `receive`.
 pub struct Receiver<'a, T> {
     channel: &'a OneshotChannel<T>,
-    /// No Send because how thread parking is implemented
-    _no_send: PhantomData<*const ()>,
 }
 
 impl<T> OneshotChannel<T> {
     pub const fn new() -> Self {
         Self {
             message: UnsafeCell::new(MaybeUninit::uninit()),
-            ready: AtomicBool::new(false),
+            waiter: UnsafeCell::new(None),
+            state: AtomicU8::new(EMPTY),
+            received: AtomicU32::new(NOT_RECEIVED),
         }
     }
 
-    pub fn split(&mut self) -> (Sender<T>, Receiver<T>) {
+    pub fn split(&mut self) -> (Sender<'_, T>, Receiver<'_, T>) {
         // In case of channel being reused after Sender and Receiving being dropped
         *self = Self::new();
+        (Sender { channel: self }, Receiver { channel: self })
+    }
+
+    /// Safety: the message must be ready, and this takes it out of the channel.
+    unsafe fn take_message(&self) -> T {
+        let message = (*self.message.get()).assume_init_read();
+        self.confirm(RECEIVED);
+        message
+    }
+
+    /// Settles whether the message was received, the first call wins.
+    fn confirm(&self, outcome: u32) {
+        let mut current = self.received.load(Relaxed);
+        while current <= CONFIRM_WAITING {
+            match self
+                .received
+                .compare_exchange_weak(current, outcome, Release, Relaxed)
+            {
+                Ok(_) => {
+                    if current == CONFIRM_WAITING {
+                        wake_one(&self.received);
+                    }
+                    return;
+                }
+                Err(e) => current = e,
+            }
+        }
+    }
+
+    /// Safety: only the side that currently owns the waiter may call this.
+    unsafe fn take_waiter(&self) -> Option<Waiter<T>> {
+        (*self.waiter.get()).take()
+    }
+}
+
+impl<T> Default for OneshotChannel<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Sender<'_, T> {
+    /// Runs the receiver's `on_ready` callback on this thread if there is one,
+    /// otherwise wakes the receiver up.
+    pub fn send(self, message: T) {
+        let channel = self.channel;
+        // Sent, so not cancelled.
+        mem::forget(self);
+        unsafe { (*channel.message.get()).write(message) };
+        // Acquire to see the waiter, if one was registered.
+        match channel.state.swap(READY, AcqRel) {
+            CALLBACK => {
+                channel.state.store(EMPTY, Relaxed);
+                if let Some(Waiter::Callback(callback)) = unsafe { channel.take_waiter() } {
+                    callback(unsafe { channel.take_message() });
+                }
+            }
+            WAITING => {
+                // The receiver never touches the waiter once it's parked.
+                if let Some(Waiter::Thread(thread)) = unsafe { channel.take_waiter() } {
+                    thread.unpark();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Like `send`, but also waits until the receiver took the message, for a synchronous
+    /// handoff. Fails if the receiver is dropped without taking it.
+    pub fn send_and_confirm(self, message: T) -> Result<(), NotReceived> {
+        let channel = self.channel;
+        self.send(message);
+        let mut received = channel.received.load(Acquire);
+        loop {
+            match received {
+                NOT_RECEIVED => {
+                    if let Err(e) = channel.received.compare_exchange(
+                        NOT_RECEIVED,
+                        CONFIRM_WAITING,
+                        Acquire,
+                        Acquire,
+                    ) {
+                        received = e;
+                        continue;
+                    }
+                }
+                CONFIRM_WAITING => {}
+                RECEIVED => return Ok(()),
+                _ => return Err(NotReceived),
+            }
+            wait(&channel.received, CONFIRM_WAITING);
+            received = channel.received.load(Acquire);
+        }
+    }
+}
+
+impl<T, E> Sender<'_, Result<T, E>> {
+    pub fn send_ok(self, value: T) {
+        self.send(Ok(value));
+    }
+
+    pub fn send_err(self, err: E) {
+        self.send(Err(err));
+    }
+}
+
+/// Dropping the sender without sending wakes the receiver up with `Cancelled`.
+impl<T> Drop for Sender<'_, T> {
+    fn drop(&mut self) {
+        match self.channel.state.swap(DISCONNECTED, AcqRel) {
+            WAITING => {
+                if let Some(Waiter::Thread(thread)) = unsafe { self.channel.take_waiter() } {
+                    thread.unpark();
+                }
+            }
+            // The message never comes, so neither does the callback.
+            CALLBACK => drop(unsafe { self.channel.take_waiter() }),
+            _ => {}
+        }
+    }
+}
+
+impl<'a, T> Receiver<'a, T> {
+    /// # Panics
+    ///
+    /// If the sender was dropped without sending, see `receive_or_cancelled`.
+    pub fn receive(self) -> T {
+        self.receive_or_cancelled()
+            .expect("the sender was dropped without sending")
+    }
+
+    pub fn receive_or_cancelled(self) -> Result<T, Cancelled> {
+        if !self.wait_ready() {
+            return Err(Cancelled);
+        }
+        Ok(unsafe { self.channel.take_message() })
+    }
+
+    /// Like `receive`, but moves the message straight into `out`
+    /// instead of returning it by value.
+    pub fn receive_into(self, out: &mut MaybeUninit<T>) -> &mut T {
+        assert!(self.wait_ready(), "the sender was dropped without sending");
+        unsafe {
+            ptr::copy_nonoverlapping((*self.channel.message.get()).as_ptr(), out.as_mut_ptr(), 1);
+            self.channel.confirm(RECEIVED);
+            out.assume_init_mut()
+        }
+    }
+
+    /// Instead of blocking, hands the message to `f` once it's sent.
+    /// `f` runs on the sending thread, or right away on this one if the message is already there.
+    /// If the sender is dropped without sending, `f` is dropped without being called.
+    pub fn on_ready(self, f: impl FnOnce(T) + Send + 'static) {
+        match self.register(Waiter::Callback(Box::new(f)), CALLBACK) {
+            // Not gone yet, the sender takes the message out for the callback.
+            Ok(()) => mem::forget(self),
+            Err((Waiter::Callback(f), READY)) => f(unsafe { self.channel.take_message() }),
+            Err(_) => {}
+        }
+    }
+
+    /// Turns the message into something else on receipt, e.g. for adapting channel types.
+    pub fn map<U, F: FnOnce(T) -> U>(self, f: F) -> MappedReceiver<'a, T, F> {
+        MappedReceiver { receiver: self, f }
+    }
+
+    /// Leaves `waiter` for the sender. Hands it back, together with the state,
+    /// if the message is already there or the sender is gone.
+    fn register(&self, waiter: Waiter<T>, state: u8) -> Result<(), (Waiter<T>, u8)> {
+        unsafe { *self.channel.waiter.get() = Some(waiter) };
+        // Release publishes the waiter to the sender.
+        // On failure the message might be there, Acquire to see it.
+        match self
+            .channel
+            .state
+            .compare_exchange(EMPTY, state, Release, Acquire)
+        {
+            Ok(_) => Ok(()),
+            Err(actual) => {
+                if actual == READY {
+                    self.channel.state.store(EMPTY, Relaxed);
+                }
+                Err((unsafe { self.channel.take_waiter() }.unwrap(), actual))
+            }
+        }
+    }
+
+    /// Returns whether the message arrived, as opposed to the sender being dropped.
+    fn wait_ready(&self) -> bool {
+        if let Err((_, state)) = self.register(Waiter::Thread(thread::current()), WAITING) {
+            return state == READY;
+        }
+        loop {
+            match self.channel.state.load(Acquire) {
+                READY => {
+                    self.channel.state.store(EMPTY, Relaxed);
+                    return true;
+                }
+                DISCONNECTED => return false,
+                _ => thread::park(),
+            }
+        }
+    }
+}
+
+/// Blocks until the first of `receivers` gets its message, and returns its index with it.
+/// If several are ready, the one that comes first wins. Receivers whose sender was dropped
+/// are skipped, it's only `Cancelled` once all of them are (or there are none).
+///
+/// The other receivers are consumed, their messages stay in their channels.
+pub fn select_all<'a, T: 'a>(
+    receivers: impl IntoIterator<Item = Receiver<'a, T>>,
+) -> Result<(usize, T), Cancelled> {
+    let receivers: Vec<_> = receivers.into_iter().collect();
+    // Register this thread with all of them, so any sender can wake it up.
+    let mut cancelled = vec![false; receivers.len()];
+    for (i, receiver) in receivers.iter().enumerate() {
+        if let Err((_, state)) = receiver.register(Waiter::Thread(thread::current()), WAITING) {
+            if state == READY {
+                return Ok((i, unsafe { receiver.channel.take_message() }));
+            }
+            cancelled[i] = true;
+        }
+    }
+    loop {
+        for (i, receiver) in receivers.iter().enumerate() {
+            match receiver.channel.state.load(Acquire) {
+                READY => {
+                    receiver.channel.state.store(EMPTY, Relaxed);
+                    return Ok((i, unsafe { receiver.channel.take_message() }));
+                }
+                DISCONNECTED => cancelled[i] = true,
+                _ => {}
+            }
+        }
+        if cancelled.iter().all(|&c| c) {
+            return Err(Cancelled);
+        }
+        thread::park();
+    }
+}
+
+/// Returned by `Receiver::map`.
+pub struct MappedReceiver<'a, T, F> {
+    receiver: Receiver<'a, T>,
+    f: F,
+}
+
+impl<'a, T, U, F: FnOnce(T) -> U> MappedReceiver<'a, T, F> {
+    /// # Panics
+    ///
+    /// If the sender was dropped without sending, see `receive_or_cancelled`.
+    pub fn receive(self) -> U {
+        (self.f)(self.receiver.receive())
+    }
+
+    /// `f` isn't called if the sender was dropped.
+    pub fn receive_or_cancelled(self) -> Result<U, Cancelled> {
+        self.receiver.receive_or_cancelled().map(self.f)
+    }
+
+    pub fn map<V>(self, g: impl FnOnce(U) -> V) -> MappedReceiver<'a, T, impl FnOnce(T) -> V> {
+        let f = self.f;
+        self.receiver.map(move |message| g(f(message)))
+    }
+}
+
+/// Lets a sender blocked in `send_and_confirm` know the message won't be taken.
+impl<T> Drop for Receiver<'_, T> {
+    fn drop(&mut self) {
+        self.channel.confirm(RECEIVER_GONE);
+    }
+}
+
+impl<T, E: From<Cancelled>> Receiver<'_, Result<T, E>> {
+    /// Like `receive`, but a dropped sender turns into an error instead of a panic.
+    pub fn receive_result(self) -> Result<T, E> {
+        self.receive_or_cancelled()
+            .unwrap_or_else(|cancelled| Err(cancelled.into()))
+    }
+}
+
+/// Recycles channels for request/response hot paths, instead of allocating one per request.
+/// A channel goes back to the pool once both of its ends are dropped.
+///
+/// The free channels form a lock-free stack, taking and returning one is a single CAS.
+///
+/// Like the channel itself, it can only be shared if messages can be sent:
+/// ```compile_fail
+/// # use rust_concurrency::primitives::oneshot_channel::OneshotPool;
+/// fn assert_sync<T: Sync>() {}
+/// assert_sync::<OneshotPool<std::rc::Rc<i32>>>();
+/// ```
+pub struct OneshotPool<T> {
+    /// Top of the stack of free slots. The lower 32 bits are the first slot's index plus one,
+    /// 0 if there's none. The upper 32 bits count pops, so that a pop racing with others can't
+    /// mistake a slot that was taken and returned in between for the one it read.
+    free: AtomicU64,
+    /// Slot `i` lives in segment `ilog2(i + 1)`, segment `k` holds `2^k` slots. Segments are
+    /// only freed with the pool, so slots stay valid even while a pop looks at a stale one.
+    segments: [AtomicPtr<PoolSlot<T>>; 32],
+    allocated: AtomicUsize,
+    /// Owns the slots, so `Send` and `Sync` follow theirs.
+    _slots: PhantomData<PoolSlot<T>>,
+}
+
+struct PoolSlot<T> {
+    channel: OneshotChannel<T>,
+    /// Ends still alive, the last one returns the slot.
+    ends: AtomicU8,
+    index: u32,
+    /// Index plus one of the next free slot, while this one is free.
+    next: AtomicU32,
+}
+
+const FREE_INDEX: u64 = u32::MAX as u64;
+const FREE_POP: u64 = 1 << 32;
+
+impl<T> OneshotPool<T> {
+    pub const fn new() -> Self {
+        Self {
+            free: AtomicU64::new(0),
+            segments: [const { AtomicPtr::new(ptr::null_mut()) }; 32],
+            allocated: AtomicUsize::new(0),
+            _slots: PhantomData,
+        }
+    }
+
+    pub fn channel(&self) -> (PooledSender<'_, T>, PooledReceiver<'_, T>) {
+        let slot = self.pop().unwrap_or_else(|| self.allocate());
+        // Safety: the slot stays allocated until both ends are done with it, and the pool
+        // outlives them. Nobody else uses its channel, so `split` may borrow it mutably.
+        // A stale pop may still read `next`, so there's no `&mut` to the whole slot.
+        let (sender, receiver) = unsafe {
+            (*slot.as_ptr()).ends.store(2, Relaxed);
+            (*ptr::addr_of_mut!((*slot.as_ptr()).channel)).split()
+        };
         (
-            Sender {
-                channel: self,
-                receiving_thread: thread::current(),
+            PooledSender {
+                sender: Some(sender),
+                slot,
+                pool: self,
             },
-            Receiver {
-                channel: self,
-                _no_send: PhantomData,
+            PooledReceiver {
+                receiver: Some(receiver),
+                slot,
+                pool: self,
             },
         )
     }
+
+    /// Number of channels taken from the allocator so far.
+    pub fn allocated(&self) -> usize {
+        self.allocated.load(Relaxed)
+    }
+
+    /// Safety: to be called exactly once by each end, after it's done with the channel.
+    unsafe fn release(&self, slot: NonNull<PoolSlot<T>>) {
+        // AcqRel, so the last end sees everything the other one did with the channel.
+        if slot.as_ref().ends.fetch_sub(1, AcqRel) == 1 {
+            // Drops a message that was sent but never received.
+            *ptr::addr_of_mut!((*slot.as_ptr()).channel) = OneshotChannel::new();
+            self.push(slot.as_ref().index);
+        }
+    }
+
+    fn slot(&self, index: u32) -> NonNull<PoolSlot<T>> {
+        let segment = (index + 1).ilog2();
+        let offset = (index + 1 - (1 << segment)) as usize;
+        let slots = self.segments[segment as usize].load(Acquire);
+        // Safety: segments are filled in before their slots are handed out, and never shrink.
+        unsafe { NonNull::new_unchecked(slots.add(offset)) }
+    }
+
+    fn pop(&self) -> Option<NonNull<PoolSlot<T>>> {
+        let mut free = self.free.load(Acquire);
+        loop {
+            let index = (free & FREE_INDEX) as u32;
+            if index == 0 {
+                return None;
+            }
+            let slot = self.slot(index - 1);
+            // Might be stale if another pop got there first, the CAS fails then.
+            let next = unsafe { slot.as_ref().next.load(Relaxed) };
+            let new = (free & !FREE_INDEX).wrapping_add(FREE_POP) | next as u64;
+            match self.free.compare_exchange_weak(free, new, Acquire, Acquire) {
+                Ok(_) => return Some(slot),
+                Err(e) => free = e,
+            }
+        }
+    }
+
+    fn push(&self, index: u32) {
+        let slot = self.slot(index);
+        let mut free = self.free.load(Relaxed);
+        loop {
+            unsafe {
+                slot.as_ref()
+                    .next
+                    .store((free & FREE_INDEX) as u32, Relaxed)
+            };
+            let new = (free & !FREE_INDEX) | (index as u64 + 1);
+            // Release publishes the reset channel and `next` to the pop taking it.
+            match self.free.compare_exchange_weak(free, new, Release, Relaxed) {
+                Ok(_) => return,
+                Err(e) => free = e,
+            }
+        }
+    }
+
+    fn allocate(&self) -> NonNull<PoolSlot<T>> {
+        let index = self.allocated.fetch_add(1, Relaxed);
+        assert!(
+            index < u32::MAX as usize - 1,
+            "too many channels in OneshotPool"
+        );
+        let index = index as u32;
+        let segment = (index + 1).ilog2();
+        let slots = &self.segments[segment as usize];
+        if slots.load(Acquire).is_null() {
+            let start = (1u32 << segment) - 1;
+            let new: Box<[PoolSlot<T>]> = (start..start + (1 << segment))
+                .map(|index| PoolSlot {
+                    channel: OneshotChannel::new(),
+                    ends: AtomicU8::new(0),
+                    index,
+                    next: AtomicU32::new(0),
+                })
+                .collect();
+            let new = Box::into_raw(new) as *mut PoolSlot<T>;
+            // Whoever needs the segment first allocates it, the others drop their copy.
+            if slots
+                .compare_exchange(ptr::null_mut(), new, AcqRel, Acquire)
+                .is_err()
+            {
+                let len = 1 << segment;
+                drop(unsafe { Box::from_raw(ptr::slice_from_raw_parts_mut(new, len)) });
+            }
+        }
+        self.slot(index)
+    }
 }
 
-impl<T> Sender<'_, T> {
-    pub fn send(self, message: T) {
-        unsafe { (*self.channel.message.get()).write(message) };
-        self.channel.ready.store(true, Release);
-        self.receiving_thread.unpark();
+impl<T> Drop for OneshotPool<T> {
+    fn drop(&mut self) {
+        for (segment, slots) in self.segments.iter_mut().enumerate() {
+            let slots = *slots.get_mut();
+            if !slots.is_null() {
+                let len = 1 << segment;
+                drop(unsafe { Box::from_raw(ptr::slice_from_raw_parts_mut(slots, len)) });
+            }
+        }
+    }
+}
+
+impl<T> Default for OneshotPool<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub struct PooledSender<'a, T> {
+    /// Only `None` once sent.
+    sender: Option<Sender<'a, T>>,
+    slot: NonNull<PoolSlot<T>>,
+    pool: &'a OneshotPool<T>,
+}
+
+unsafe impl<T: Send> Send for PooledSender<'_, T> {}
+
+pub struct PooledReceiver<'a, T> {
+    /// Only `None` once received.
+    receiver: Option<Receiver<'a, T>>,
+    slot: NonNull<PoolSlot<T>>,
+    pool: &'a OneshotPool<T>,
+}
+
+unsafe impl<T: Send> Send for PooledReceiver<'_, T> {}
+
+impl<T> PooledSender<'_, T> {
+    pub fn send(mut self, message: T) {
+        self.sender.take().unwrap().send(message);
     }
 }
 
-impl<T> Receiver<'_, T> {
+impl<T> PooledReceiver<'_, T> {
+    /// # Panics
+    ///
+    /// If the sender was dropped without sending, see `receive_or_cancelled`.
     pub fn receive(self) -> T {
-        while !self.channel.ready.swap(false, Acquire) {
-            thread::park();
-        }
-        unsafe { (*self.channel.message.get()).assume_init_read() }
+        self.receive_or_cancelled()
+            .expect("the sender was dropped without sending")
+    }
+
+    pub fn receive_or_cancelled(mut self) -> Result<T, Cancelled> {
+        // The channel goes back to the pool once `self` is dropped, after receiving.
+        self.receiver.take().unwrap().receive_or_cancelled()
+    }
+}
+
+impl<T> Drop for PooledSender<'_, T> {
+    fn drop(&mut self) {
+        // Disconnects if nothing was sent.
+        drop(self.sender.take());
+        unsafe { self.pool.release(self.slot) };
+    }
+}
+
+impl<T> Drop for PooledReceiver<'_, T> {
+    fn drop(&mut self) {
+        // Done with the channel before it might be reused.
+        drop(self.receiver.take());
+        unsafe { self.pool.release(self.slot) };
+    }
+}
+
+/// The sender was dropped without sending anything.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cancelled;
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        "the sender was dropped without sending".fmt(f)
     }
 }
 
+impl Error for Cancelled {}
+
+/// The receiver was dropped without taking the message, see `Sender::send_and_confirm`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NotReceived;
+
+impl fmt::Display for NotReceived {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        "the receiver was dropped without receiving".fmt(f)
+    }
+}
+
+impl Error for NotReceived {}
+
 impl<T> Drop for OneshotChannel<T> {
     fn drop(&mut self) {
-        if *self.ready.get_mut() {
+        if *self.state.get_mut() == READY {
             unsafe { (*self.message.get()).assume_init_drop() }
         }
     }
@@ -78,8 +620,13 @@ impl<T> Drop for OneshotChannel<T> {
 
 #[cfg(test)]
 mod test {
-    use super::OneshotChannel;
-    use std::thread;
+    use super::{select_all, Cancelled, NotReceived, OneshotChannel, OneshotPool, PooledSender};
+    use std::{
+        mem::MaybeUninit,
+        sync::mpsc,
+        thread,
+        time::{Duration, Instant},
+    };
 
     #[test]
     fn test() {
@@ -92,4 +639,332 @@ mod test {
             assert_eq!(receiver.receive(), "test");
         })
     }
+
+    #[test]
+    fn test_send_receiver() {
+        let mut channel = OneshotChannel::new();
+        thread::scope(|s| {
+            let (sender, receiver) = channel.split();
+            let receiving = s.spawn(move || receiver.receive());
+            // Give the receiver a chance to park first.
+            thread::yield_now();
+            sender.send(1);
+            assert_eq!(receiving.join().unwrap(), 1);
+        });
+        thread::scope(|s| {
+            let (sender, receiver) = channel.split();
+            sender.send(2);
+            assert_eq!(s.spawn(move || receiver.receive()).join().unwrap(), 2);
+        })
+    }
+
+    #[test]
+    fn test_receive_into() {
+        struct Large {
+            id: u32,
+            payload: [u64; 512],
+        }
+
+        let mut channel = OneshotChannel::new();
+        let mut out = MaybeUninit::<Large>::uninit();
+        thread::scope(|s| {
+            let (sender, receiver) = channel.split();
+            s.spawn(move || {
+                let mut payload = [0; 512];
+                payload
+                    .iter_mut()
+                    .enumerate()
+                    .for_each(|(i, v)| *v = i as u64);
+                sender.send(Large { id: 7, payload });
+            });
+            let received = receiver.receive_into(&mut out);
+            assert_eq!(received.id, 7);
+        });
+        let large = unsafe { out.assume_init() };
+        assert_eq!(large.id, 7);
+        assert!(large
+            .payload
+            .iter()
+            .enumerate()
+            .all(|(i, v)| *v == i as u64));
+    }
+
+    #[test]
+    fn test_on_ready_before_send() {
+        let mut channel = OneshotChannel::new();
+        let (tx, rx) = mpsc::channel();
+        let sending_thread = thread::scope(|s| {
+            let (sender, receiver) = channel.split();
+            receiver.on_ready(move |message| {
+                tx.send((message, thread::current().id())).unwrap();
+            });
+            s.spawn(move || {
+                sender.send("test");
+                thread::current().id()
+            })
+            .join()
+            .unwrap()
+        });
+        assert_eq!(rx.recv().unwrap(), ("test", sending_thread));
+        // The callback ran exactly once and is gone.
+        assert!(rx.recv().is_err());
+    }
+
+    #[test]
+    fn test_on_ready_after_send() {
+        let mut channel = OneshotChannel::new();
+        let (tx, rx) = mpsc::channel();
+        let (sender, receiver) = channel.split();
+        sender.send(String::from("test"));
+        receiver.on_ready(move |message| {
+            tx.send((message, thread::current().id())).unwrap();
+        });
+        assert_eq!(
+            rx.recv().unwrap(),
+            (String::from("test"), thread::current().id())
+        );
+        assert!(rx.recv().is_err());
+    }
+
+    #[derive(Debug, PartialEq)]
+    enum RpcError {
+        Failed(&'static str),
+        Cancelled,
+    }
+
+    impl From<Cancelled> for RpcError {
+        fn from(_: Cancelled) -> Self {
+            RpcError::Cancelled
+        }
+    }
+
+    #[test]
+    fn test_result() {
+        let mut channel = OneshotChannel::<Result<u32, RpcError>>::new();
+        thread::scope(|s| {
+            let (sender, receiver) = channel.split();
+            s.spawn(move || sender.send_ok(1));
+            assert_eq!(receiver.receive_result(), Ok(1));
+        });
+        thread::scope(|s| {
+            let (sender, receiver) = channel.split();
+            s.spawn(move || sender.send_err(RpcError::Failed("no such method")));
+            assert_eq!(
+                receiver.receive_result(),
+                Err(RpcError::Failed("no such method"))
+            );
+        });
+        thread::scope(|s| {
+            let (sender, receiver) = channel.split();
+            s.spawn(move || drop(sender));
+            assert_eq!(receiver.receive_result(), Err(RpcError::Cancelled));
+        });
+    }
+
+    #[test]
+    fn test_cancelled() {
+        let mut channel = OneshotChannel::<String>::new();
+        let (sender, receiver) = channel.split();
+        drop(sender);
+        assert_eq!(receiver.receive_or_cancelled(), Err(Cancelled));
+
+        let (tx, rx) = mpsc::channel();
+        let (sender, receiver) = channel.split();
+        receiver.on_ready(move |message| tx.send(message).unwrap());
+        drop(sender);
+        // The callback was dropped without running.
+        assert!(rx.recv().is_err());
+    }
+
+    #[test]
+    fn test_send_and_confirm() {
+        let mut channel = OneshotChannel::new();
+        thread::scope(|s| {
+            let (sender, receiver) = channel.split();
+            let start = Instant::now();
+            s.spawn(move || {
+                thread::sleep(Duration::from_millis(50));
+                assert_eq!(receiver.receive(), 1);
+            });
+            assert_eq!(sender.send_and_confirm(1), Ok(()));
+            assert!(start.elapsed() >= Duration::from_millis(50));
+        });
+
+        // The callback takes the message right away, on this thread.
+        let (sender, receiver) = channel.split();
+        let (tx, rx) = mpsc::channel();
+        receiver.on_ready(move |n| tx.send(n).unwrap());
+        assert_eq!(sender.send_and_confirm(2), Ok(()));
+        assert_eq!(rx.recv(), Ok(2));
+    }
+
+    #[test]
+    fn test_send_and_confirm_not_received() {
+        let mut channel = OneshotChannel::new();
+        thread::scope(|s| {
+            let (sender, receiver) = channel.split();
+            s.spawn(move || {
+                thread::sleep(Duration::from_millis(20));
+                drop(receiver);
+            });
+            assert_eq!(
+                sender.send_and_confirm(String::from("dropped")),
+                Err(NotReceived)
+            );
+        });
+
+        let (sender, receiver) = channel.split();
+        drop(receiver);
+        assert_eq!(sender.send_and_confirm(String::new()), Err(NotReceived));
+    }
+
+    #[test]
+    fn test_map() {
+        let mut channel = OneshotChannel::new();
+        thread::scope(|s| {
+            let (sender, receiver) = channel.split();
+            let receiver = receiver.map(|n: i32| n.to_string());
+            s.spawn(move || sender.send(42));
+            assert_eq!(receiver.receive(), "42");
+        });
+
+        let (sender, receiver) = channel.split();
+        let receiver = receiver.map(|n| n * 2).map(|n| format!("<{n}>"));
+        sender.send(21);
+        assert_eq!(receiver.receive_or_cancelled().unwrap(), "<42>");
+
+        let (sender, receiver) = channel.split();
+        drop(sender);
+        let receiver = receiver.map(|_| -> String { unreachable!() });
+        assert_eq!(receiver.receive_or_cancelled(), Err(Cancelled));
+    }
+
+    #[test]
+    fn test_select_all() {
+        let mut slow = OneshotChannel::new();
+        let mut fast = OneshotChannel::new();
+        thread::scope(|s| {
+            let (slow_sender, slow_receiver) = slow.split();
+            let (fast_sender, fast_receiver) = fast.split();
+            s.spawn(move || {
+                thread::sleep(Duration::from_millis(200));
+                slow_sender.send("slow");
+            });
+            s.spawn(move || fast_sender.send("fast"));
+            assert_eq!(select_all([slow_receiver, fast_receiver]), Ok((1, "fast")));
+        });
+
+        // Both ready, the first one wins.
+        let (sender_a, receiver_a) = slow.split();
+        let (sender_b, receiver_b) = fast.split();
+        sender_b.send("b");
+        sender_a.send("a");
+        assert_eq!(select_all([receiver_a, receiver_b]), Ok((0, "a")));
+
+        // Cancelled ones are skipped.
+        thread::scope(|s| {
+            let (sender_a, receiver_a) = slow.split();
+            let (sender_b, receiver_b) = fast.split();
+            drop(sender_a);
+            s.spawn(move || {
+                thread::sleep(Duration::from_millis(50));
+                sender_b.send("b");
+            });
+            assert_eq!(select_all([receiver_a, receiver_b]), Ok((1, "b")));
+        });
+        let (sender_a, receiver_a) = slow.split();
+        let (sender_b, receiver_b) = fast.split();
+        drop((sender_a, sender_b));
+        assert_eq!(select_all([receiver_a, receiver_b]), Err(Cancelled));
+    }
+
+    #[test]
+    fn test_stress() {
+        // Small enough for Miri, which checks that the message is published before the state.
+        let rounds = if cfg!(miri) { 20 } else { 2000 };
+        let mut channels: Vec<OneshotChannel<Vec<usize>>> =
+            (0..4).map(|_| OneshotChannel::new()).collect();
+        for round in 0..rounds {
+            thread::scope(|s| {
+                let mut receivers = Vec::new();
+                for (i, channel) in channels.iter_mut().enumerate() {
+                    let (sender, receiver) = channel.split();
+                    s.spawn(move || sender.send(vec![round, i]));
+                    receivers.push(receiver);
+                }
+                for (i, receiver) in receivers.into_iter().enumerate() {
+                    if i % 2 == 0 {
+                        assert_eq!(receiver.receive(), [round, i]);
+                    } else {
+                        assert_eq!(
+                            s.spawn(move || receiver.receive()).join().unwrap(),
+                            [round, i]
+                        );
+                    }
+                }
+            });
+        }
+    }
+
+    #[test]
+    fn test_pool() {
+        let pool = OneshotPool::new();
+        thread::scope(|s| {
+            let (requests, incoming) = mpsc::channel::<(u32, PooledSender<u32>)>();
+            s.spawn(move || {
+                for (request, responder) in incoming {
+                    responder.send(request * 2);
+                }
+            });
+            for i in 0..1000 {
+                let (sender, receiver) = pool.channel();
+                requests.send((i, sender)).unwrap();
+                assert_eq!(receiver.receive(), i * 2);
+            }
+        });
+        // The responder may still hold the previous channel when the next one is taken.
+        assert!(pool.allocated() <= 2);
+
+        // Returned with the message unread, or without anything sent.
+        let pool = OneshotPool::new();
+        let drops = std::sync::Arc::new(());
+        let (sender, receiver) = pool.channel();
+        sender.send(drops.clone());
+        drop(receiver);
+        assert_eq!(std::sync::Arc::strong_count(&drops), 1);
+        let (sender, receiver) = pool.channel();
+        drop(sender);
+        assert_eq!(receiver.receive_or_cancelled(), Err(Cancelled));
+        assert_eq!(pool.allocated(), 1);
+    }
+
+    #[test]
+    fn test_pool_concurrent() {
+        let pool = OneshotPool::new();
+        thread::scope(|s| {
+            for t in 0..4 {
+                let pool = &pool;
+                s.spawn(move || {
+                    for i in 0..500 {
+                        // Several at once, so slots come from more than one segment.
+                        let channels: Vec<_> = (0..5).map(|_| pool.channel()).collect();
+                        for (j, (sender, receiver)) in channels.into_iter().enumerate() {
+                            let message = (t, i, j);
+                            thread::scope(|s| {
+                                s.spawn(move || sender.send(message));
+                            });
+                            assert_eq!(receiver.receive(), message);
+                        }
+                    }
+                });
+            }
+        });
+        // Every thread holds at most five at a time.
+        let allocated = pool.allocated();
+        assert!(allocated <= 20);
+        // All of them are back on the free list.
+        let channels: Vec<_> = (0..allocated).map(|_| pool.channel()).collect();
+        assert_eq!(pool.allocated(), allocated);
+        drop(channels);
+    }
 }
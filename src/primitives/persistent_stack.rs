@@ -0,0 +1,153 @@
+use std::mem;
+
+use super::arc::Arc;
+
+/// Immutable stack whose versions share their common tail, so pushing and popping are O(1)
+/// and never copy the rest. Versions can be handed to other threads freely.
+pub struct PersistentStack<T> {
+    head: Option<Arc<Node<T>>>,
+    len: usize,
+}
+
+struct Node<T> {
+    value: T,
+    next: Option<Arc<Node<T>>>,
+}
+
+impl<T> PersistentStack<T> {
+    pub const fn new() -> Self {
+        Self { head: None, len: 0 }
+    }
+
+    /// A new version with `value` on top, this one stays as it is.
+    pub fn push(&self, value: T) -> PersistentStack<T> {
+        PersistentStack {
+            head: Some(Arc::new(Node {
+                value,
+                next: self.head.clone(),
+            })),
+            len: self.len + 1,
+        }
+    }
+
+    pub fn peek(&self) -> Option<&T> {
+        self.head.as_ref().map(|node| &node.value)
+    }
+
+    /// The version below the top, without cloning the top value.
+    pub fn tail(&self) -> PersistentStack<T> {
+        match &self.head {
+            Some(node) => PersistentStack {
+                head: node.next.clone(),
+                len: self.len - 1,
+            },
+            None => PersistentStack::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+impl<T: Clone> PersistentStack<T> {
+    /// The top value, cloned since other versions may still share it, and the version below.
+    pub fn pop(&self) -> Option<(T, PersistentStack<T>)> {
+        let value = self.peek()?.clone();
+        Some((value, self.tail()))
+    }
+}
+
+impl<T> Clone for PersistentStack<T> {
+    fn clone(&self) -> Self {
+        Self {
+            head: self.head.clone(),
+            len: self.len,
+        }
+    }
+}
+
+impl<T> Default for PersistentStack<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Drop for PersistentStack<T> {
+    fn drop(&mut self) {
+        // Unlink the nodes only this version holds one by one, instead of recursively,
+        // which could overflow the stack for long ones.
+        let mut head = self.head.take();
+        while let Some(mut node) = head {
+            match node.get_mut() {
+                Some(node) => head = mem::take(&mut node.next),
+                None => break,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::PersistentStack;
+    use crate::primitives::arc::Arc;
+    use std::thread;
+
+    fn shares_tail<T>(a: &PersistentStack<T>, b: &PersistentStack<T>) -> bool {
+        let (a, b) = (a.tail(), b.tail());
+        Arc::ptr_eq(a.head.as_ref().unwrap(), b.head.as_ref().unwrap())
+    }
+
+    #[test]
+    fn test() {
+        let base = PersistentStack::new().push(1).push(2);
+        let a = base.push(3);
+        let b = base.push(4).push(5);
+        assert_eq!((base.len(), a.len(), b.len()), (2, 3, 4));
+        assert!(shares_tail(&a, &b.tail()));
+
+        let (top, rest) = b.pop().unwrap();
+        assert_eq!(top, 5);
+        assert_eq!(rest.peek(), Some(&4));
+        assert!(Arc::ptr_eq(
+            rest.tail().head.as_ref().unwrap(),
+            base.head.as_ref().unwrap()
+        ));
+
+        // Versions are independent of each other.
+        drop(base);
+        let collected = thread::scope(|s| {
+            let a = &a;
+            s.spawn(move || {
+                let mut values = Vec::new();
+                let mut stack = a.clone();
+                while let Some((value, rest)) = stack.pop() {
+                    values.push(value);
+                    stack = rest;
+                }
+                values
+            })
+            .join()
+            .unwrap()
+        });
+        assert_eq!(collected, [3, 2, 1]);
+        assert!(PersistentStack::<i32>::new().pop().is_none());
+    }
+
+    #[test]
+    fn test_long() {
+        let mut stack = PersistentStack::new();
+        for i in 0..100_000 {
+            stack = stack.push(i);
+        }
+        let shared = stack.tail().tail();
+        drop(stack);
+        assert_eq!(shared.peek(), Some(&99_997));
+        // Neither this nor the drop above recurses through all the nodes.
+        drop(shared);
+    }
+}
@@ -0,0 +1,126 @@
+use std::{
+    error::Error,
+    fmt,
+    sync::atomic::{AtomicBool, Ordering::Relaxed},
+    thread,
+};
+
+/// Marks a lock as poisoned once a guard is dropped while its thread is panicking.
+pub(crate) struct Flag {
+    failed: AtomicBool,
+}
+
+impl Flag {
+    pub(crate) const fn new() -> Self {
+        Self {
+            failed: AtomicBool::new(false),
+        }
+    }
+
+    /// To be taken whenever the lock is acquired.
+    pub(crate) fn guard(&self) -> Guard {
+        Guard {
+            panicking: thread::panicking(),
+        }
+    }
+
+    /// To be called before the lock is released.
+    pub(crate) fn done(&self, guard: &Guard) {
+        // Only a panic that started while the lock was held poisons it.
+        if !guard.panicking && thread::panicking() {
+            self.failed.store(true, Relaxed);
+        }
+    }
+
+    pub(crate) fn get(&self) -> bool {
+        self.failed.load(Relaxed)
+    }
+
+    /// Carries poisoning over when a lock is turned into another one.
+    pub(crate) fn set(&self) {
+        self.failed.store(true, Relaxed);
+    }
+
+    pub(crate) fn clear(&self) {
+        self.failed.store(false, Relaxed);
+    }
+}
+
+#[derive(Clone, Copy)]
+pub(crate) struct Guard {
+    panicking: bool,
+}
+
+/// The lock was acquired, but a thread panicked while holding it before.
+/// Same as `std::sync::PoisonError`, the guard can still be recovered.
+pub struct PoisonError<T> {
+    guard: T,
+}
+
+impl<T> PoisonError<T> {
+    pub fn new(guard: T) -> Self {
+        Self { guard }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.guard
+    }
+
+    pub fn get_ref(&self) -> &T {
+        &self.guard
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.guard
+    }
+}
+
+impl<T> fmt::Debug for PoisonError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PoisonError").finish_non_exhaustive()
+    }
+}
+
+impl<T> fmt::Display for PoisonError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        "poisoned lock: another task failed inside".fmt(f)
+    }
+}
+
+impl<T> Error for PoisonError<T> {}
+
+/// Why a `try_*` lock call failed, same as `std::sync::TryLockError`.
+pub enum TryLockError<T> {
+    Poisoned(PoisonError<T>),
+    WouldBlock,
+}
+
+impl<T> From<PoisonError<T>> for TryLockError<T> {
+    fn from(err: PoisonError<T>) -> Self {
+        TryLockError::Poisoned(err)
+    }
+}
+
+impl<T> fmt::Debug for TryLockError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TryLockError::Poisoned(err) => err.fmt(f),
+            TryLockError::WouldBlock => "WouldBlock".fmt(f),
+        }
+    }
+}
+
+impl<T> fmt::Display for TryLockError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TryLockError::Poisoned(err) => err.fmt(f),
+            TryLockError::WouldBlock => "try_lock failed because the operation would block".fmt(f),
+        }
+    }
+}
+
+impl<T> Error for TryLockError<T> {}
+
+pub type LockResult<Guard> = Result<Guard, PoisonError<Guard>>;
+
+pub type TryLockResult<Guard> = Result<Guard, TryLockError<Guard>>;
@@ -0,0 +1,133 @@
+use std::time::{Duration, Instant};
+
+use super::{condvar::Condvar, mutex::Mutex};
+
+/// Token bucket: hands out one token every `interval`, and saves up to `capacity`
+/// of them while nobody asks, so short bursts don't have to wait.
+pub struct RateLimiter {
+    bucket: Mutex<Bucket>,
+    /// Only used to sleep until the next token without holding the lock.
+    condvar: Condvar,
+    capacity: u32,
+    interval: Duration,
+}
+
+struct Bucket {
+    tokens: u32,
+    /// When the last token was added. Time that didn't add up to a full token yet
+    /// is kept, so the rate doesn't drift with how often the bucket is checked.
+    last_refill: Instant,
+}
+
+impl RateLimiter {
+    /// Starts with a full bucket.
+    ///
+    /// # Panics
+    ///
+    /// If `capacity` or `interval` is zero. With no room for a token, `acquire` would
+    /// never return.
+    pub fn new(capacity: u32, interval: Duration) -> Self {
+        assert!(capacity > 0, "RateLimiter capacity must not be zero");
+        assert!(!interval.is_zero(), "RateLimiter interval must not be zero");
+        Self {
+            bucket: Mutex::new(Bucket {
+                tokens: capacity,
+                last_refill: Instant::now(),
+            }),
+            condvar: Condvar::new(),
+            capacity,
+            interval,
+        }
+    }
+
+    /// Blocks until a token is available and takes it.
+    pub fn acquire(&self) {
+        let mut bucket = self.bucket.lock();
+        loop {
+            self.refill(&mut bucket);
+            if bucket.tokens > 0 {
+                bucket.tokens -= 1;
+                return;
+            }
+            let next = bucket.last_refill + self.interval;
+            bucket = self.condvar.wait_deadline(bucket, next).0;
+        }
+    }
+
+    pub fn try_acquire(&self) -> bool {
+        let mut bucket = self.bucket.lock();
+        self.refill(&mut bucket);
+        if bucket.tokens == 0 {
+            return false;
+        }
+        bucket.tokens -= 1;
+        true
+    }
+
+    fn refill(&self, bucket: &mut Bucket) {
+        let now = Instant::now();
+        let elapsed = now.saturating_duration_since(bucket.last_refill);
+        let new_tokens = elapsed.as_nanos() / self.interval.as_nanos();
+        let missing = self.capacity - bucket.tokens;
+        if new_tokens >= u128::from(missing) {
+            // Full, there's nothing to save up time for.
+            bucket.tokens = self.capacity;
+            bucket.last_refill = now;
+        } else {
+            // Smaller than `missing`, so it fits.
+            let new_tokens = new_tokens as u32;
+            bucket.tokens += new_tokens;
+            bucket.last_refill += self.interval * new_tokens;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::RateLimiter;
+    use std::{
+        panic::catch_unwind,
+        thread,
+        time::{Duration, Instant},
+    };
+
+    #[test]
+    fn test() {
+        let limiter = RateLimiter::new(1, Duration::from_millis(20));
+        let start = Instant::now();
+        thread::scope(|s| {
+            for _ in 0..2 {
+                s.spawn(|| {
+                    for _ in 0..5 {
+                        limiter.acquire();
+                    }
+                });
+            }
+        });
+        // The first token was already there, the other 9 took 20ms each.
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(180), "{elapsed:?}");
+        assert!(elapsed < Duration::from_secs(1), "{elapsed:?}");
+    }
+
+    #[test]
+    fn test_try_acquire() {
+        let limiter = RateLimiter::new(3, Duration::from_millis(50));
+        assert!((0..3).all(|_| limiter.try_acquire()));
+        assert!(!limiter.try_acquire());
+
+        thread::sleep(Duration::from_millis(60));
+        assert!(limiter.try_acquire());
+        assert!(!limiter.try_acquire());
+
+        // Saves up at most `capacity` tokens.
+        thread::sleep(Duration::from_millis(250));
+        assert!((0..3).all(|_| limiter.try_acquire()));
+        assert!(!limiter.try_acquire());
+    }
+
+    #[test]
+    fn test_zero_capacity() {
+        assert!(catch_unwind(|| RateLimiter::new(0, Duration::from_millis(1))).is_err());
+    }
+}
@@ -0,0 +1,255 @@
+use std::{
+    cell::{Cell, UnsafeCell},
+    mem::{self, ManuallyDrop},
+    ops::Deref,
+    ptr::{self, NonNull},
+};
+
+/// Single-threaded counterpart of this crate's `Arc`, the counters don't need atomics.
+/// It can't be sent to or shared with another thread:
+/// ```compile_fail
+/// # use rust_concurrency::primitives::rc::Rc;
+/// fn assert_send<T: Send>() {}
+/// assert_send::<Rc<i32>>();
+/// ```
+/// ```compile_fail
+/// # use rust_concurrency::primitives::rc::Rc;
+/// fn assert_sync<T: Sync>() {}
+/// assert_sync::<Rc<i32>>();
+/// ```
+pub struct Rc<T: ?Sized> {
+    ptr: NonNull<RcData<T>>,
+}
+
+pub struct RcWeak<T: ?Sized> {
+    ptr: NonNull<RcData<T>>,
+}
+
+struct RcData<T: ?Sized> {
+    /// Number of `Rc`s
+    strong: Cell<usize>,
+    /// Number of `RcWeak`s, plus one if there is any `Rc`
+    weak: Cell<usize>,
+    /// Dropped if there are no `Rc`s left.
+    data: UnsafeCell<ManuallyDrop<T>>,
+}
+
+impl<T> Rc<T> {
+    pub fn new(data: T) -> Rc<T> {
+        Rc {
+            ptr: NonNull::from(Box::leak(Box::new(RcData {
+                strong: Cell::new(1),
+                weak: Cell::new(1),
+                data: UnsafeCell::new(ManuallyDrop::new(data)),
+            }))),
+        }
+    }
+
+    /// Takes the value out if this is the only `Rc`, `RcWeak`s then fail to upgrade.
+    pub fn try_unwrap(this: Rc<T>) -> Result<T, Rc<T>> {
+        if this.data().strong.get() != 1 {
+            return Err(this);
+        }
+        this.data().strong.set(0);
+        let ptr = this.ptr;
+        mem::forget(this);
+        // Safety: the strong counter is zero, nothing else can reach the value anymore.
+        let value = unsafe { ManuallyDrop::take(&mut *(*ptr.as_ptr()).data.get()) };
+        drop(RcWeak { ptr });
+        Ok(value)
+    }
+}
+
+impl<T: ?Sized> Rc<T> {
+    pub fn get_mut(&mut self) -> Option<&mut T> {
+        // No other thread can change the counters in between, unlike for `Arc`.
+        if self.data().strong.get() != 1 || self.data().weak.get() != 1 {
+            return None;
+        }
+        unsafe { Some(&mut *self.data().data.get()) }
+    }
+
+    pub fn downgrade(&self) -> RcWeak<T> {
+        let weak = &self.data().weak;
+        weak.set(weak.get().checked_add(1).unwrap());
+        RcWeak { ptr: self.ptr }
+    }
+
+    pub fn strong_count(this: &Rc<T>) -> usize {
+        this.data().strong.get()
+    }
+
+    /// Number of `RcWeak`s, without the implicit one shared by all `Rc`s.
+    pub fn weak_count(this: &Rc<T>) -> usize {
+        this.data().weak.get() - 1
+    }
+
+    /// Whether both point to the same allocation, not just equal values.
+    pub fn ptr_eq(this: &Rc<T>, other: &Rc<T>) -> bool {
+        ptr::addr_eq(this.ptr.as_ptr(), other.ptr.as_ptr())
+    }
+
+    fn data(&self) -> &RcData<T> {
+        unsafe { self.ptr.as_ref() }
+    }
+}
+
+impl<T: ?Sized> Deref for Rc<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.data().data.get() }
+    }
+}
+
+impl<T: ?Sized> Clone for Rc<T> {
+    fn clone(&self) -> Self {
+        let strong = &self.data().strong;
+        strong.set(strong.get().checked_add(1).unwrap());
+        Rc { ptr: self.ptr }
+    }
+}
+
+impl<T: ?Sized> Drop for Rc<T> {
+    fn drop(&mut self) {
+        let strong = &self.data().strong;
+        strong.set(strong.get() - 1);
+        if strong.get() == 0 {
+            // Safety: Strong counter is zero, nothing can access the data anymore.
+            unsafe {
+                ManuallyDrop::drop(&mut *self.data().data.get());
+            }
+            // No `Rc`s left, drop the implicit weak pointer that represents all `Rc`s.
+            drop(RcWeak { ptr: self.ptr });
+        }
+    }
+}
+
+impl<T: ?Sized> RcWeak<T> {
+    pub fn upgrade(&self) -> Option<Rc<T>> {
+        let strong = &self.data().strong;
+        if strong.get() == 0 {
+            return None;
+        }
+        strong.set(strong.get() + 1);
+        Some(Rc { ptr: self.ptr })
+    }
+
+    fn data(&self) -> &RcData<T> {
+        unsafe { self.ptr.as_ref() }
+    }
+}
+
+impl<T: ?Sized> Clone for RcWeak<T> {
+    fn clone(&self) -> Self {
+        let weak = &self.data().weak;
+        weak.set(weak.get().checked_add(1).unwrap());
+        RcWeak { ptr: self.ptr }
+    }
+}
+
+impl<T: ?Sized> Drop for RcWeak<T> {
+    fn drop(&mut self) {
+        let weak = &self.data().weak;
+        weak.set(weak.get() - 1);
+        if weak.get() == 0 {
+            // Safety: Weak counter is zero, nothing can access the pointer anymore.
+            unsafe {
+                drop(Box::from_raw(self.ptr.as_ptr()));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::cell::RefCell;
+
+    struct CountDrop<'a>(&'a Cell<usize>);
+
+    impl Drop for CountDrop<'_> {
+        fn drop(&mut self) {
+            self.0.set(self.0.get() + 1);
+        }
+    }
+
+    fn check_counters<T: ?Sized>(ptr: NonNull<RcData<T>>, exp_strong: usize, exp_weak: usize) {
+        assert_eq!(unsafe { ptr.as_ref().strong.get() }, exp_strong);
+        assert_eq!(unsafe { ptr.as_ref().weak.get() }, exp_weak);
+    }
+
+    #[test]
+    fn test_various() {
+        let drops = Cell::new(0);
+        let mut strong = Rc::new(("hello", CountDrop(&drops)));
+        assert!(strong.get_mut().is_some());
+
+        let weak1 = strong.downgrade();
+        let weak2 = weak1.clone();
+        assert!(strong.get_mut().is_none());
+        check_counters(weak1.ptr, 1, 3);
+
+        let temp_strong = weak1.upgrade().unwrap();
+        drop(weak1);
+        assert_eq!(temp_strong.0, "hello");
+        check_counters(temp_strong.ptr, 2, 2);
+        assert!(Rc::ptr_eq(&strong, &temp_strong));
+        drop(temp_strong);
+
+        drop(weak2);
+        assert_eq!(drops.get(), 0);
+        assert_eq!((Rc::strong_count(&strong), Rc::weak_count(&strong)), (1, 0));
+        assert!(strong.get_mut().is_some());
+
+        let weak3 = strong.downgrade();
+        drop(strong);
+        assert_eq!(drops.get(), 1);
+        check_counters(weak3.ptr, 0, 1);
+        assert!(weak3.upgrade().is_none());
+    }
+
+    #[test]
+    fn test_try_unwrap() {
+        let drops = Cell::new(0);
+        let rc = Rc::new(CountDrop(&drops));
+        let cloned = rc.clone();
+        let Err(rc) = Rc::try_unwrap(rc) else {
+            panic!("unwrapped a shared Rc");
+        };
+        drop(cloned);
+
+        let weak = rc.downgrade();
+        let value = Rc::try_unwrap(rc).ok().unwrap();
+        assert!(weak.upgrade().is_none());
+        check_counters(weak.ptr, 0, 1);
+        assert_eq!(drops.get(), 0);
+        drop(value);
+        assert_eq!(drops.get(), 1);
+    }
+
+    #[test]
+    fn test_cycle() {
+        let drops = Cell::new(0);
+        struct Node<'a> {
+            parent: RefCell<Option<RcWeak<Node<'a>>>>,
+            children: RefCell<Vec<Rc<Node<'a>>>>,
+            _drop: CountDrop<'a>,
+        }
+
+        let node = || Node {
+            parent: RefCell::new(None),
+            children: RefCell::new(Vec::new()),
+            _drop: CountDrop(&drops),
+        };
+        {
+            let parent = Rc::new(node());
+            let child = Rc::new(node());
+            *child.parent.borrow_mut() = Some(parent.downgrade());
+            parent.children.borrow_mut().push(child.clone());
+            let upgraded = child.parent.borrow().as_ref().unwrap().upgrade().unwrap();
+            assert!(Rc::ptr_eq(&upgraded, &parent));
+        }
+        assert_eq!(drops.get(), 2);
+    }
+}
@@ -1,64 +1,257 @@
 use std::{
     cell::UnsafeCell,
+    fmt, mem,
     ops::{Deref, DerefMut},
     sync::atomic::{AtomicU32, Ordering::*},
+    thread,
 };
 
 use atomic_wait::{wait, wake_all, wake_one};
+#[cfg(feature = "rwlock-stats")]
+use std::sync::atomic::AtomicU64;
+
+#[cfg(feature = "lock-tracing")]
+use super::lock_trace::{trace, LockOp};
+use super::{
+    cache_padded::CachePadded,
+    mutex::Mutex,
+    poison::{self, LockResult, PoisonError, TryLockError, TryLockResult},
+};
 
 pub struct RwLock<T> {
-    /// Number of read locks time two, plus one if there's a writer waiting.
+    /// Number of read locks times `READER`, plus `UPGRADABLE` if one of them is the upgradable
+    /// read lock, plus one if there's a writer waiting.
     /// u32::MAX if locked by a writer.
-    state: AtomicU32,
+    state: CachePadded<AtomicU32>,
     /// Incremented to wake up writers.
     write_wake_counter: AtomicU32,
+    /// Writer acquisitions in a row after which waiting readers get their turn, 0 if disabled.
+    max_consecutive_writes: u32,
+    /// Writer acquisitions since a reader last got the lock, only tracked when fair.
+    consecutive_writes: AtomicU32,
+    /// Number of readers blocked waiting for the lock.
+    waiting_readers: AtomicU32,
+    /// Number of threads blocked waiting for the upgradable read lock to be released.
+    upgradable_waiters: AtomicU32,
+    /// How many times to yield to the lock holder before going to sleep.
+    yields_before_wait: u32,
+    poison: poison::Flag,
+    /// Shown in `Debug` and panic messages.
+    name: Option<&'static str>,
+    #[cfg(feature = "rwlock-stats")]
+    stats: Counters,
     value: UnsafeCell<T>,
 }
 
+const READER: u32 = 4;
+const UPGRADABLE: u32 = 2;
+/// Keeps the state below u32::MAX, which means write-locked.
+const MAX_READERS: u32 = (u32::MAX >> 2) - 1;
+
 unsafe impl<T> Sync for RwLock<T> where T: Send + Sync {}
 
 impl<T> RwLock<T> {
     pub const fn new(data: T) -> Self {
+        Self::new_eventually_fair(data, 0)
+    }
+
+    /// Creates a lock that doesn't let writers starve readers: once writers acquired it
+    /// `max_consecutive_writes` times in a row, waiting readers are let in before the next writer.
+    /// A waiting reader is therefore served after at most `max_consecutive_writes` writer
+    /// acquisitions, plus one for each writer that was already racing for the lock.
+    ///
+    /// `0` disables this, which gives the plain writer-preferring lock of `new`.
+    pub const fn new_eventually_fair(data: T, max_consecutive_writes: u32) -> Self {
         Self {
-            state: AtomicU32::new(0),
+            state: CachePadded::new(AtomicU32::new(0)),
             write_wake_counter: AtomicU32::new(0),
+            max_consecutive_writes,
+            consecutive_writes: AtomicU32::new(0),
+            waiting_readers: AtomicU32::new(0),
+            upgradable_waiters: AtomicU32::new(0),
+            yields_before_wait: 0,
+            poison: poison::Flag::new(),
+            name: None,
+            #[cfg(feature = "rwlock-stats")]
+            stats: Counters::new(),
             value: UnsafeCell::new(data),
         }
     }
 
-    pub fn read(&self) -> ReadGuard<T> {
+    /// `N` independent locks, e.g. for striping a sharded structure.
+    /// `init` gets each lock's index.
+    pub fn new_array<const N: usize>(mut init: impl FnMut(usize) -> T) -> [Self; N] {
+        std::array::from_fn(|i| Self::new(init(i)))
+    }
+
+    /// Labels the lock for debugging, see `Debug`.
+    pub const fn with_name(data: T, name: &'static str) -> Self {
+        let mut rwlock = Self::new(data);
+        rwlock.name = Some(name);
+        rwlock
+    }
+
+    pub fn name(&self) -> Option<&'static str> {
+        self.name
+    }
+
+    /// Makes waiting threads yield up to `yields` times before going to sleep, `0` turns it off.
+    ///
+    /// This gives a preempted lock holder (e.g. a low priority writer) CPU time to finish
+    /// instead of everyone queueing up behind it. It's only a mitigation of priority inversion,
+    /// proper priority inheritance needs support from the OS scheduler.
+    pub const fn with_yielding(mut self, yields: u32) -> Self {
+        self.yields_before_wait = yields;
+        self
+    }
+
+    /// Waits for pending writers, so a thread that already holds a read lock deadlocks
+    /// if a writer arrives in between its two `read` calls. Use `read_recursive` for that.
+    ///
+    /// # Panics
+    ///
+    /// With around two billion readers holding the lock at once, the reader count would overflow.
+    /// Use `try_read` to get an error instead.
+    pub fn read(&self) -> ReadGuard<'_, T> {
+        self.read_impl(false)
+    }
+
+    /// Like `read`, but goes ahead of pending writers, so a thread already holding a read lock
+    /// can always lock again. Used for anything but that, it can starve writers.
+    pub fn read_recursive(&self) -> ReadGuard<'_, T> {
+        self.read_impl(true)
+    }
+
+    fn read_impl(&self, recursive: bool) -> ReadGuard<'_, T> {
         let mut state = self.state.load(Relaxed);
         loop {
-            // No active / pending writers, okay to lock
-            if state % 2 == 0 {
-                assert!(state < u32::MAX - 2, "too many readers");
+            // No active / pending writers, okay to lock.
+            // When it's the readers' turn, a pending writer doesn't hold them back either.
+            if state.is_multiple_of(2) || (state != u32::MAX && (recursive || self.readers_turn()))
+            {
+                assert!(
+                    state >> 2 < MAX_READERS,
+                    "too many readers on RwLock {}",
+                    self.name.unwrap_or("<unnamed>")
+                );
                 match self
                     .state
-                    .compare_exchange_weak(state, state + 2, Acquire, Relaxed)
+                    .compare_exchange_weak(state, state + READER, Acquire, Relaxed)
                 {
-                    Ok(_) => return ReadGuard { rwlock: self },
-                    Err(e) => state = e,
+                    Ok(_) => {
+                        if self.max_consecutive_writes != 0 {
+                            self.consecutive_writes.store(0, Relaxed);
+                        }
+                        #[cfg(feature = "rwlock-stats")]
+                        self.stats.reads.fetch_add(1, Relaxed);
+                        let guard = ReadGuard { rwlock: self };
+                        #[cfg(feature = "lock-tracing")]
+                        trace(LockOp::Read, self, self.name);
+                        return guard;
+                    }
+                    Err(e) => {
+                        state = e;
+                        continue;
+                    }
                 }
             }
 
             // Pending writer, wait so writers are not starved
             if state % 2 == 1 {
-                wait(&self.state, state);
+                if !self.yield_until_changed(&self.state, state) {
+                    self.waiting_readers.fetch_add(1, Relaxed);
+                    #[cfg(feature = "rwlock-stats")]
+                    self.stats.reader_waits.fetch_add(1, Relaxed);
+                    wait(&self.state, state);
+                    self.waiting_readers.fetch_sub(1, Relaxed);
+                }
                 state = self.state.load(Relaxed);
             }
         }
     }
 
-    pub fn write(&self) -> WriteGuard<T> {
+    /// A read lock that can later be upgraded to the write lock without unlocking in between.
+    /// Only one thread at a time can hold it, while plain readers still get in alongside.
+    /// Waits for pending writers like `read`.
+    pub fn upgradable_read(&self) -> UpgradableReadGuard<'_, T> {
         let mut state = self.state.load(Relaxed);
         loop {
+            // u32::MAX has the upgradable bit set too, so this also rules out a writer.
+            if state & UPGRADABLE == 0 && (state.is_multiple_of(2) || self.readers_turn()) {
+                assert!(
+                    state >> 2 < MAX_READERS,
+                    "too many readers on RwLock {}",
+                    self.name.unwrap_or("<unnamed>")
+                );
+                match self.state.compare_exchange_weak(
+                    state,
+                    state + READER + UPGRADABLE,
+                    Acquire,
+                    Relaxed,
+                ) {
+                    Ok(_) => {
+                        if self.max_consecutive_writes != 0 {
+                            self.consecutive_writes.store(0, Relaxed);
+                        }
+                        #[cfg(feature = "rwlock-stats")]
+                        self.stats.reads.fetch_add(1, Relaxed);
+                        let guard = UpgradableReadGuard { rwlock: self };
+                        #[cfg(feature = "lock-tracing")]
+                        trace(LockOp::Read, self, self.name);
+                        return guard;
+                    }
+                    Err(e) => {
+                        state = e;
+                        continue;
+                    }
+                }
+            }
+
+            if state % 2 == 1 {
+                // Behind a writer, same as `read`.
+                self.waiting_readers.fetch_add(1, Relaxed);
+                wait(&self.state, state);
+                self.waiting_readers.fetch_sub(1, Relaxed);
+            } else {
+                // SeqCst, so either the holder sees us waiting or we see it leave.
+                self.upgradable_waiters.fetch_add(1, SeqCst);
+                wait(&self.state, state);
+                self.upgradable_waiters.fetch_sub(1, SeqCst);
+            }
+            state = self.state.load(Relaxed);
+        }
+    }
+
+    pub fn write(&self) -> WriteGuard<'_, T> {
+        let mut state = self.state.load(Relaxed);
+        loop {
+            // Readers' turn, step aside until one of the waiting readers gets in
+            if state != u32::MAX && self.readers_turn() && self.waiting_readers.load(Relaxed) != 0 {
+                thread::yield_now();
+                state = self.state.load(Relaxed);
+                continue;
+            }
+
             // No readers, try to lock
             if state <= 1 {
                 match self
                     .state
                     .compare_exchange(state, u32::MAX, Acquire, Relaxed)
                 {
-                    Ok(_) => return WriteGuard { rwlock: self },
+                    Ok(_) => {
+                        if self.max_consecutive_writes != 0 {
+                            self.consecutive_writes.fetch_add(1, Relaxed);
+                        }
+                        #[cfg(feature = "rwlock-stats")]
+                        self.stats.writes.fetch_add(1, Relaxed);
+                        let guard = WriteGuard {
+                            rwlock: self,
+                            poison: self.poison.guard(),
+                        };
+                        #[cfg(feature = "lock-tracing")]
+                        trace(LockOp::Write, self, self.name);
+                        return guard;
+                    }
                     Err(e) => {
                         state = e;
                         continue;
@@ -68,7 +261,7 @@ impl<T> RwLock<T> {
 
             // Inform the readers about waiting writer
             // u32::MAX is odd so this won't be executed when another writer locks it
-            if state % 2 == 0 {
+            if state.is_multiple_of(2) {
                 if let Err(e) = self
                     .state
                     .compare_exchange(state, state + 1, Relaxed, Relaxed)
@@ -76,23 +269,407 @@ impl<T> RwLock<T> {
                     state = e;
                     continue;
                 }
+                #[cfg(feature = "rwlock-stats")]
+                self.stats.writer_pending_sets.fetch_add(1, Relaxed);
             }
 
-            // Locked by someone else, need to wait
+            // Locked by someone else, need to wait.
+            // The state has to be checked again after loading the counter: if the lock was
+            // released before that, the wake-up already happened and waiting on `w` would miss it.
+            // A release after the counter load moves it past `w`, so `wait` returns right away.
             let w = self.write_wake_counter.load(Acquire);
             state = self.state.load(Relaxed);
             if state >= 2 {
-                wait(&self.write_wake_counter, w);
+                if !self.yield_until_changed(&self.write_wake_counter, w) {
+                    #[cfg(feature = "rwlock-stats")]
+                    self.stats.writer_waits.fetch_add(1, Relaxed);
+                    wait(&self.write_wake_counter, w);
+                }
                 state = self.state.load(Relaxed);
             }
         }
     }
+
+    /// Also fails with `WouldBlock` if there are too many readers to count another one.
+    pub fn try_read(&self) -> TryLockResult<ReadGuard<'_, T>> {
+        let mut state = self.state.load(Relaxed);
+        while state.is_multiple_of(2) || (state != u32::MAX && self.readers_turn()) {
+            if state >> 2 >= MAX_READERS {
+                break;
+            }
+            match self
+                .state
+                .compare_exchange_weak(state, state + READER, Acquire, Relaxed)
+            {
+                Ok(_) => {
+                    if self.max_consecutive_writes != 0 {
+                        self.consecutive_writes.store(0, Relaxed);
+                    }
+                    #[cfg(feature = "rwlock-stats")]
+                    self.stats.reads.fetch_add(1, Relaxed);
+                    let guard = ReadGuard { rwlock: self };
+                    #[cfg(feature = "lock-tracing")]
+                    trace(LockOp::Read, self, self.name);
+                    if self.poison.get() {
+                        return Err(TryLockError::Poisoned(PoisonError::new(guard)));
+                    }
+                    return Ok(guard);
+                }
+                Err(e) => state = e,
+            }
+        }
+        Err(TryLockError::WouldBlock)
+    }
+
+    pub fn try_write(&self) -> TryLockResult<WriteGuard<'_, T>> {
+        let mut state = self.state.load(Relaxed);
+        while state <= 1 {
+            match self
+                .state
+                .compare_exchange(state, u32::MAX, Acquire, Relaxed)
+            {
+                Ok(_) => {
+                    if self.max_consecutive_writes != 0 {
+                        self.consecutive_writes.fetch_add(1, Relaxed);
+                    }
+                    #[cfg(feature = "rwlock-stats")]
+                    self.stats.writes.fetch_add(1, Relaxed);
+                    let guard = WriteGuard {
+                        rwlock: self,
+                        poison: self.poison.guard(),
+                    };
+                    #[cfg(feature = "lock-tracing")]
+                    trace(LockOp::Write, self, self.name);
+                    if self.poison.get() {
+                        return Err(TryLockError::Poisoned(PoisonError::new(guard)));
+                    }
+                    return Ok(guard);
+                }
+                Err(e) => state = e,
+            }
+        }
+        Err(TryLockError::WouldBlock)
+    }
+
+    /// Whether a thread panicked while holding the write lock.
+    /// `read` and `write` don't report this, the `try_*` variants do.
+    pub fn is_poisoned(&self) -> bool {
+        self.poison.get()
+    }
+
+    pub fn clear_poison(&self) {
+        self.poison.clear();
+    }
+
+    /// Read-locks, computes a value from the data and unlocks again.
+    /// The guard never leaves this function, only the result does.
+    pub fn read_map<R>(&self, f: impl FnOnce(&T) -> R) -> R {
+        f(&self.read())
+    }
+
+    /// Like `read_map`, under the write lock.
+    pub fn write_map<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
+        f(&mut self.write())
+    }
+
+    /// Collects mutations to apply later under a single, short write lock, see `Staged`.
+    pub fn stage(&self) -> Staged<'_, T> {
+        Staged {
+            rwlock: self,
+            mutations: Vec::new(),
+        }
+    }
+
+    /// Runs `f` with the read lock held if it's available, `None` if it isn't.
+    /// Like `read`, this doesn't report poisoning.
+    pub fn try_with_read<R>(&self, f: impl FnOnce(&T) -> R) -> Option<R> {
+        let guard = match self.try_read() {
+            Ok(guard) => guard,
+            Err(TryLockError::Poisoned(err)) => err.into_inner(),
+            Err(TryLockError::WouldBlock) => return None,
+        };
+        Some(f(&guard))
+    }
+
+    /// Runs `f` with the write lock held if it's available, `None` if it isn't.
+    /// Like `write`, this doesn't report poisoning.
+    pub fn try_with_write<R>(&self, f: impl FnOnce(&mut T) -> R) -> Option<R> {
+        let mut guard = match self.try_write() {
+            Ok(guard) => guard,
+            Err(TryLockError::Poisoned(err)) => err.into_inner(),
+            Err(TryLockError::WouldBlock) => return None,
+        };
+        Some(f(&mut guard))
+    }
+
+    /// Like `read`, but if the lock is poisoned, first repairs the data
+    /// with `recover` under the write lock and clears the poison.
+    pub fn read_or_else(&self, recover: impl FnOnce(&mut T)) -> ReadGuard<'_, T> {
+        if self.poison.get() {
+            drop(self.write_or_else(recover));
+        }
+        self.read()
+    }
+
+    /// Like `write`, but if the lock is poisoned, repairs the data
+    /// with `recover` and clears the poison before returning the guard.
+    pub fn write_or_else(&self, recover: impl FnOnce(&mut T)) -> WriteGuard<'_, T> {
+        let mut guard = self.write();
+        if self.poison.get() {
+            recover(&mut guard);
+            self.poison.clear();
+        }
+        guard
+    }
+
+    /// No locking needed, the `&mut` already proves nobody else has access.
+    /// Reports poisoning like `Mutex::get_mut`, and like for `Mutex`, there's no `from_mut`
+    /// turning a `&mut T` into a lock.
+    pub fn get_mut(&mut self) -> LockResult<&mut T> {
+        let poisoned = self.poison.get();
+        let value = self.value.get_mut();
+        if poisoned {
+            return Err(PoisonError::new(value));
+        }
+        Ok(value)
+    }
+
+    /// Reports poisoning like `get_mut`.
+    pub fn into_inner(self) -> LockResult<T> {
+        let poisoned = self.poison.get();
+        let value = self.value.into_inner();
+        if poisoned {
+            return Err(PoisonError::new(value));
+        }
+        Ok(value)
+    }
+
+    /// Keeps the value, the name and the poisoning, the rest is `Mutex::new`'s defaults.
+    pub fn into_mutex(self) -> Mutex<T> {
+        let name = self.name;
+        let (value, poisoned) = match self.into_inner() {
+            Ok(value) => (value, false),
+            Err(err) => (err.into_inner(), true),
+        };
+        let mutex = match name {
+            Some(name) => Mutex::with_name(value, name),
+            None => Mutex::new(value),
+        };
+        if poisoned {
+            mutex.poison();
+        }
+        mutex
+    }
+
+    pub(crate) fn poison(&self) {
+        self.poison.set();
+    }
+
+    /// Raw pointer to the protected data, for building custom guards.
+    /// Accessing it is only sound while holding the lock, and writing only with the write lock.
+    pub fn data_ptr(&self) -> *mut T {
+        self.value.get()
+    }
+
+    /// Counters for tuning the writer preference, taken with `Relaxed` loads,
+    /// so they're only consistent with each other once the lock is idle.
+    #[cfg(feature = "rwlock-stats")]
+    pub fn stats(&self) -> RwLockStats {
+        RwLockStats {
+            reads: self.stats.reads.load(Relaxed),
+            writes: self.stats.writes.load(Relaxed),
+            reader_waits: self.stats.reader_waits.load(Relaxed),
+            writer_waits: self.stats.writer_waits.load(Relaxed),
+            writer_pending_sets: self.stats.writer_pending_sets.load(Relaxed),
+        }
+    }
+
+    /// Yields up to `yields_before_wait` times, returning whether `atomic` moved on from `value`.
+    fn yield_until_changed(&self, atomic: &AtomicU32, value: u32) -> bool {
+        for _ in 0..self.yields_before_wait {
+            thread::yield_now();
+            if atomic.load(Relaxed) != value {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Wakes up whoever waits for the readers to leave, given the state after a reader left.
+    fn wake_writers_after_read(&self, state: u32) {
+        if state == 1 {
+            self.write_wake_counter.fetch_add(1, Release);
+            wake_one(&self.write_wake_counter);
+        } else if state == READER + UPGRADABLE + 1 {
+            // Only the upgradable reader is left. If it's upgrading, it has to be the one
+            // woken up, it's not known which of the waiting threads it is.
+            self.write_wake_counter.fetch_add(1, Release);
+            wake_all(&self.write_wake_counter);
+        }
+    }
+
+    fn wake_upgradable_waiters(&self) {
+        if self.upgradable_waiters.load(SeqCst) != 0 {
+            wake_all(&*self.state);
+        }
+    }
+
+    /// Whether writers got the lock often enough in a row to let the waiting readers in.
+    fn readers_turn(&self) -> bool {
+        self.max_consecutive_writes != 0
+            && self.consecutive_writes.load(Relaxed) >= self.max_consecutive_writes
+    }
+}
+
+impl<T: Default> RwLock<T> {
+    /// Moves the value out under the write lock, and stores what `f` turns it into.
+    /// If `f` panics, the value stays `T::default()`, and the lock is poisoned.
+    pub fn replace_with(&self, f: impl FnOnce(T) -> T) {
+        let mut guard = self.write();
+        let value = mem::take(&mut *guard);
+        *guard = f(value);
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for RwLock<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut d = f.debug_struct("RwLock");
+        if let Some(name) = self.name {
+            d.field("name", &name);
+        }
+        match self.try_read() {
+            Ok(guard) => d.field("data", &&*guard),
+            Err(TryLockError::Poisoned(err)) => d.field("data", &&**err.get_ref()),
+            Err(TryLockError::WouldBlock) => d.field("data", &format_args!("<locked>")),
+        };
+        d.field("poisoned", &self.is_poisoned());
+        d.finish_non_exhaustive()
+    }
+}
+
+/// See `RwLock::stats`.
+#[cfg(feature = "rwlock-stats")]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RwLockStats {
+    /// Read locks acquired, including by `try_read`.
+    pub reads: u64,
+    /// Write locks acquired, including by `try_write`.
+    pub writes: u64,
+    /// Times a reader went to sleep behind a writer.
+    pub reader_waits: u64,
+    /// Times a writer went to sleep behind the lock holder.
+    pub writer_waits: u64,
+    /// Times a writer marked itself as pending, holding back new readers.
+    pub writer_pending_sets: u64,
 }
 
+#[cfg(feature = "rwlock-stats")]
+struct Counters {
+    reads: AtomicU64,
+    writes: AtomicU64,
+    reader_waits: AtomicU64,
+    writer_waits: AtomicU64,
+    writer_pending_sets: AtomicU64,
+}
+
+#[cfg(feature = "rwlock-stats")]
+impl Counters {
+    const fn new() -> Self {
+        Self {
+            reads: AtomicU64::new(0),
+            writes: AtomicU64::new(0),
+            reader_waits: AtomicU64::new(0),
+            writer_waits: AtomicU64::new(0),
+            writer_pending_sets: AtomicU64::new(0),
+        }
+    }
+}
+
+/// Returned by `RwLock::stage`. Reads go through the read lock as usual, while mutations
+/// only pile up until `commit` applies them all at once, so readers see either all or none.
+/// Dropping it without committing discards them.
+pub struct Staged<'a, T> {
+    rwlock: &'a RwLock<T>,
+    mutations: Vec<Mutation<'a, T>>,
+}
+
+type Mutation<'a, T> = Box<dyn FnOnce(&mut T) + 'a>;
+
+impl<'a, T> Staged<'a, T> {
+    /// The data as of now, without any of the staged mutations.
+    pub fn read(&self) -> ReadGuard<'a, T> {
+        self.rwlock.read()
+    }
+
+    pub fn push(&mut self, mutation: impl FnOnce(&mut T) + 'a) {
+        self.mutations.push(Box::new(mutation));
+    }
+
+    pub fn len(&self) -> usize {
+        self.mutations.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.mutations.is_empty()
+    }
+
+    /// Applies the mutations in the order they were staged.
+    pub fn commit(self) {
+        if self.mutations.is_empty() {
+            return;
+        }
+        let mut guard = self.rwlock.write();
+        for mutation in self.mutations {
+            mutation(&mut guard);
+        }
+    }
+}
+
+/// Write-locks both, always in address order, so that two threads locking the same pair
+/// in opposite argument order can't deadlock. The guards come back in argument order.
+///
+/// # Panics
+///
+/// If `a` and `b` are the same lock.
+pub fn write_both<'a, A, B>(
+    a: &'a RwLock<A>,
+    b: &'a RwLock<B>,
+) -> (WriteGuard<'a, A>, WriteGuard<'a, B>) {
+    let (a_addr, b_addr) = (a as *const _ as usize, b as *const _ as usize);
+    assert_ne!(a_addr, b_addr, "write_both called with the same lock twice");
+    if a_addr < b_addr {
+        let a = a.write();
+        (a, b.write())
+    } else {
+        let b = b.write();
+        (a.write(), b)
+    }
+}
+
+/// Sending a read guard shares `&T` with the other thread, so it requires `T: Sync`,
+/// and `T: Send` for the lock to be shared in the first place:
+/// ```
+/// # use rust_concurrency::primitives::rwlock::ReadGuard;
+/// fn assert_send_sync<T: Send + Sync>() {}
+/// assert_send_sync::<ReadGuard<'static, i32>>();
+/// ```
+/// ```compile_fail
+/// # use rust_concurrency::primitives::rwlock::ReadGuard;
+/// fn assert_send<T: Send>() {}
+/// assert_send::<ReadGuard<'static, std::cell::Cell<i32>>>();
+/// ```
+/// ```compile_fail
+/// # use rust_concurrency::primitives::rwlock::ReadGuard;
+/// fn assert_sync<T: Sync>() {}
+/// assert_sync::<ReadGuard<'static, std::cell::Cell<i32>>>();
+/// ```
 pub struct ReadGuard<'a, T> {
     rwlock: &'a RwLock<T>,
 }
 
+// Same as `std::sync::RwLockReadGuard`, sharing the guard only shares `&T`.
+unsafe impl<T> Sync for ReadGuard<'_, T> where T: Sync {}
+
 impl<T> Deref for ReadGuard<'_, T> {
     type Target = T;
 
@@ -101,17 +678,208 @@ impl<T> Deref for ReadGuard<'_, T> {
     }
 }
 
+/// Counts as another reader, without waiting for pending writers like `read_recursive`,
+/// since the lock is already held. The lock is released once all clones are dropped.
+impl<T> Clone for ReadGuard<'_, T> {
+    fn clone(&self) -> Self {
+        let rwlock = self.rwlock;
+        let mut state = rwlock.state.load(Relaxed);
+        loop {
+            assert!(
+                state >> 2 < MAX_READERS,
+                "too many readers on RwLock {}",
+                rwlock.name.unwrap_or("<unnamed>")
+            );
+            match rwlock
+                .state
+                .compare_exchange_weak(state, state + READER, Relaxed, Relaxed)
+            {
+                Ok(_) => break,
+                Err(e) => state = e,
+            }
+        }
+        #[cfg(feature = "rwlock-stats")]
+        rwlock.stats.reads.fetch_add(1, Relaxed);
+        let guard = ReadGuard { rwlock };
+        #[cfg(feature = "lock-tracing")]
+        trace(LockOp::Read, rwlock, rwlock.name);
+        guard
+    }
+}
+
 impl<T> Drop for ReadGuard<'_, T> {
     fn drop(&mut self) {
-        if self.rwlock.state.fetch_sub(2, Release) == 3 {
-            self.rwlock.write_wake_counter.fetch_add(1, Release);
-            wake_one(&self.rwlock.write_wake_counter);
+        #[cfg(feature = "lock-tracing")]
+        trace(LockOp::ReadUnlock, self.rwlock, self.rwlock.name);
+        let state = self.rwlock.state.fetch_sub(READER, Release) - READER;
+        self.rwlock.wake_writers_after_read(state);
+    }
+}
+
+/// Returned by `RwLock::upgradable_read`, and `WriteGuard::downgrade_to_upgradable`.
+pub struct UpgradableReadGuard<'a, T> {
+    rwlock: &'a RwLock<T>,
+}
+
+unsafe impl<T> Sync for UpgradableReadGuard<'_, T> where T: Sync {}
+
+impl<'a, T> UpgradableReadGuard<'a, T> {
+    /// Waits for the other readers to leave and takes the write lock, without unlocking
+    /// in between. New readers are held back in the meantime, like for a waiting writer.
+    pub fn upgrade(self) -> WriteGuard<'a, T> {
+        let rwlock = self.rwlock;
+        mem::forget(self);
+        let mut state = rwlock.state.load(Relaxed);
+        loop {
+            // Writers can't get in while we still count as a reader, only readers have to leave.
+            if state & !1 == READER + UPGRADABLE {
+                match rwlock
+                    .state
+                    .compare_exchange(state, u32::MAX, Acquire, Relaxed)
+                {
+                    Ok(_) => break,
+                    Err(e) => {
+                        state = e;
+                        continue;
+                    }
+                }
+            }
+
+            if state.is_multiple_of(2) {
+                if let Err(e) = rwlock
+                    .state
+                    .compare_exchange(state, state + 1, Relaxed, Relaxed)
+                {
+                    state = e;
+                    continue;
+                }
+            }
+
+            // Same as in `write`, the state has to be checked again after loading the counter.
+            let w = rwlock.write_wake_counter.load(Acquire);
+            state = rwlock.state.load(Relaxed);
+            if state & !1 != READER + UPGRADABLE {
+                wait(&rwlock.write_wake_counter, w);
+                state = rwlock.state.load(Relaxed);
+            }
+        }
+        #[cfg(feature = "rwlock-stats")]
+        rwlock.stats.writes.fetch_add(1, Relaxed);
+        let guard = WriteGuard {
+            rwlock,
+            poison: rwlock.poison.guard(),
+        };
+        #[cfg(feature = "lock-tracing")]
+        {
+            trace(LockOp::ReadUnlock, rwlock, rwlock.name);
+            trace(LockOp::Write, rwlock, rwlock.name);
         }
+        guard
+    }
+
+    /// Gives up the option to upgrade, letting another thread take the upgradable read lock,
+    /// but stays a reader.
+    pub fn downgrade(self) -> ReadGuard<'a, T> {
+        let rwlock = self.rwlock;
+        mem::forget(self);
+        rwlock.state.fetch_sub(UPGRADABLE, SeqCst);
+        rwlock.wake_upgradable_waiters();
+        ReadGuard { rwlock }
     }
 }
 
+impl<T> Deref for UpgradableReadGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.rwlock.value.get() }
+    }
+}
+
+impl<T> Drop for UpgradableReadGuard<'_, T> {
+    fn drop(&mut self) {
+        #[cfg(feature = "lock-tracing")]
+        trace(LockOp::ReadUnlock, self.rwlock, self.rwlock.name);
+        let state = self.rwlock.state.fetch_sub(READER + UPGRADABLE, SeqCst) - READER - UPGRADABLE;
+        self.rwlock.wake_writers_after_read(state);
+        self.rwlock.wake_upgradable_waiters();
+    }
+}
+
+/// ```
+/// # use rust_concurrency::primitives::rwlock::WriteGuard;
+/// fn assert_send_sync<T: Send + Sync>() {}
+/// assert_send_sync::<WriteGuard<'static, i32>>();
+/// ```
+/// ```compile_fail
+/// # use rust_concurrency::primitives::rwlock::WriteGuard;
+/// fn assert_send<T: Send>() {}
+/// assert_send::<WriteGuard<'static, std::rc::Rc<i32>>>();
+/// ```
+/// ```compile_fail
+/// # use rust_concurrency::primitives::rwlock::WriteGuard;
+/// fn assert_sync<T: Sync>() {}
+/// assert_sync::<WriteGuard<'static, std::cell::Cell<i32>>>();
+/// ```
 pub struct WriteGuard<'a, T> {
     rwlock: &'a RwLock<T>,
+    poison: poison::Guard,
+}
+
+// Same as `std::sync::RwLockWriteGuard`, sharing the guard only shares `&T`.
+unsafe impl<T> Sync for WriteGuard<'_, T> where T: Sync {}
+
+impl<'a, T> WriteGuard<'a, T> {
+    /// Gives up the guard without unlocking, so the lock stays write-locked forever
+    /// and the returned reference is the only way left to reach the data.
+    pub fn leak(self) -> &'a mut T {
+        let value = self.rwlock.value.get();
+        mem::forget(self);
+        unsafe { &mut *value }
+    }
+
+    /// Turns the write lock into a read lock without unlocking in between,
+    /// so no writer can get in and change what was just written.
+    pub fn downgrade(self) -> ReadGuard<'a, T> {
+        let rwlock = self.rwlock;
+        #[cfg(feature = "lock-tracing")]
+        trace(LockOp::WriteUnlock, rwlock, rwlock.name);
+        rwlock.poison.done(&self.poison);
+        mem::forget(self);
+        if rwlock.max_consecutive_writes != 0 {
+            rwlock.consecutive_writes.store(0, Relaxed);
+        }
+        // One reader, any writer waiting has to mark itself pending again.
+        rwlock.state.store(READER, Release);
+        rwlock.write_wake_counter.fetch_add(1, Release);
+        wake_one(&rwlock.write_wake_counter);
+        wake_all(&*rwlock.state);
+        let guard = ReadGuard { rwlock };
+        #[cfg(feature = "lock-tracing")]
+        trace(LockOp::Read, rwlock, rwlock.name);
+        guard
+    }
+
+    /// Like `downgrade`, but keeps the option to upgrade again: the lock goes straight to
+    /// the upgradable read lock, other readers get in while writers still don't.
+    pub fn downgrade_to_upgradable(self) -> UpgradableReadGuard<'a, T> {
+        let rwlock = self.rwlock;
+        #[cfg(feature = "lock-tracing")]
+        trace(LockOp::WriteUnlock, rwlock, rwlock.name);
+        rwlock.poison.done(&self.poison);
+        mem::forget(self);
+        if rwlock.max_consecutive_writes != 0 {
+            rwlock.consecutive_writes.store(0, Relaxed);
+        }
+        rwlock.state.store(READER + UPGRADABLE, Release);
+        rwlock.write_wake_counter.fetch_add(1, Release);
+        wake_one(&rwlock.write_wake_counter);
+        wake_all(&*rwlock.state);
+        let guard = UpgradableReadGuard { rwlock };
+        #[cfg(feature = "lock-tracing")]
+        trace(LockOp::Read, rwlock, rwlock.name);
+        guard
+    }
 }
 
 impl<T> Deref for WriteGuard<'_, T> {
@@ -130,19 +898,26 @@ impl<T> DerefMut for WriteGuard<'_, T> {
 
 impl<T> Drop for WriteGuard<'_, T> {
     fn drop(&mut self) {
+        #[cfg(feature = "lock-tracing")]
+        trace(LockOp::WriteUnlock, self.rwlock, self.rwlock.name);
+        self.rwlock.poison.done(&self.poison);
         self.rwlock.state.store(0, Release);
         self.rwlock.write_wake_counter.fetch_add(1, Release);
 
         wake_one(&self.rwlock.write_wake_counter);
-        wake_all(&self.rwlock.state);
+        wake_all(&*self.rwlock.state);
     }
 }
 
 #[cfg(test)]
 mod test {
-    use std::thread;
+    use std::{
+        sync::atomic::{AtomicBool, AtomicU32, Ordering::Relaxed},
+        thread,
+        time::{Duration, Instant},
+    };
 
-    use super::RwLock;
+    use super::{write_both, RwLock, TryLockError, MAX_READERS, READER};
 
     #[test]
     fn test() {
@@ -181,4 +956,551 @@ mod test {
 
         assert_eq!(*rwlock.read(), 200);
     }
+
+    #[test]
+    fn test_eventually_fair() {
+        let writers = 3;
+        let max_consecutive_writes = 4;
+        let rwlock = RwLock::new_eventually_fair(0, max_consecutive_writes);
+        // Writer acquisitions while the reader was waiting
+        let writes_while_waiting = AtomicU32::new(0);
+        let stop = AtomicBool::new(false);
+
+        thread::scope(|s| {
+            for _ in 0..writers {
+                s.spawn(|| {
+                    while !stop.load(Relaxed) {
+                        let mut val = rwlock.write();
+                        if rwlock.waiting_readers.load(Relaxed) != 0 {
+                            writes_while_waiting.fetch_add(1, Relaxed);
+                        }
+                        *val += 1;
+                    }
+                });
+            }
+
+            for _ in 0..200 {
+                let _val = rwlock.read();
+                assert!(writes_while_waiting.swap(0, Relaxed) <= max_consecutive_writes + writers);
+            }
+            stop.store(true, Relaxed);
+        });
+    }
+
+    #[test]
+    fn test_try_lock() {
+        let rwlock = RwLock::new(0);
+        {
+            let _r = rwlock.read();
+            assert!(rwlock.try_read().is_ok());
+            assert!(matches!(rwlock.try_write(), Err(TryLockError::WouldBlock)));
+        }
+        {
+            let _w = rwlock.write();
+            assert!(matches!(rwlock.try_read(), Err(TryLockError::WouldBlock)));
+            assert!(matches!(rwlock.try_write(), Err(TryLockError::WouldBlock)));
+        }
+        *rwlock.try_write().unwrap() += 1;
+
+        thread::scope(|s| {
+            let panicked = s.spawn(|| {
+                let _w = rwlock.write();
+                panic!("poisoning the rwlock");
+            });
+            assert!(panicked.join().is_err());
+        });
+        assert!(rwlock.is_poisoned());
+        let result = rwlock.try_read();
+        match result {
+            Err(TryLockError::Poisoned(err)) => assert_eq!(*err.into_inner(), 1),
+            _ => panic!("expected a poisoned rwlock"),
+        }
+        assert!(matches!(rwlock.try_write(), Err(TryLockError::Poisoned(_))));
+    }
+
+    #[test]
+    fn test_try_read_saturated() {
+        let rwlock = RwLock::new(0);
+        // Pretend there are almost as many readers as fit in the state.
+        rwlock.state.store((MAX_READERS - 1) * READER, Relaxed);
+        let r = rwlock.try_read().unwrap();
+        assert!(matches!(rwlock.try_read(), Err(TryLockError::WouldBlock)));
+        drop(r);
+        assert!(rwlock.try_read().is_ok());
+        rwlock.state.store(0, Relaxed);
+        assert_eq!(*rwlock.write(), 0);
+    }
+
+    #[test]
+    fn test_write_both() {
+        let a = RwLock::new(0);
+        let b = RwLock::new(String::new());
+        thread::scope(|s| {
+            s.spawn(|| {
+                for _ in 0..1000 {
+                    let (mut a, mut b) = write_both(&a, &b);
+                    *a += 1;
+                    b.push('a');
+                }
+            });
+            s.spawn(|| {
+                for _ in 0..1000 {
+                    let (mut b, mut a) = write_both(&b, &a);
+                    *a += 1;
+                    b.push('b');
+                }
+            });
+        });
+        assert_eq!(*a.read(), 2000);
+        assert_eq!(b.read().len(), 2000);
+    }
+
+    #[test]
+    fn test_read_recursive() {
+        let rwlock = RwLock::new(0);
+        thread::scope(|s| {
+            let r1 = rwlock.read();
+            let writer = s.spawn(|| *rwlock.write() += 1);
+            while rwlock.state.load(Relaxed).is_multiple_of(2) {
+                thread::yield_now();
+            }
+            // A plain `read` would wait for the writer, which waits for `r1`.
+            assert!(matches!(rwlock.try_read(), Err(TryLockError::WouldBlock)));
+            let r2 = rwlock.read_recursive();
+            assert_eq!((*r1, *r2), (0, 0));
+            drop((r1, r2));
+            writer.join().unwrap();
+        });
+        assert_eq!(*rwlock.read_recursive(), 1);
+    }
+
+    #[test]
+    fn test_get_mut() {
+        let mut rwlock = RwLock::new(1);
+        *rwlock.get_mut().unwrap() += 1;
+        assert_eq!(*rwlock.read(), 2);
+
+        thread::scope(|s| {
+            let panicked = s.spawn(|| {
+                let _g = rwlock.write();
+                panic!("poisoning the rwlock");
+            });
+            assert!(panicked.join().is_err());
+        });
+        *rwlock.get_mut().unwrap_err().into_inner() += 1;
+        rwlock.clear_poison();
+        *rwlock.get_mut().unwrap() += 1;
+        assert_eq!(*rwlock.try_read().unwrap(), 4);
+    }
+
+    #[test]
+    fn test_into_mutex() {
+        let rwlock = RwLock::with_name(vec![1, 2], "numbers");
+        rwlock.write().push(3);
+        let mutex = rwlock.into_mutex();
+        assert_eq!(mutex.name(), Some("numbers"));
+        assert!(!mutex.is_poisoned());
+        assert_eq!(*mutex.lock(), [1, 2, 3]);
+        assert_eq!(mutex.into_rwlock().into_inner().unwrap(), [1, 2, 3]);
+
+        let rwlock = RwLock::new(5);
+        thread::scope(|s| {
+            let panicked = s.spawn(|| {
+                let _g = rwlock.write();
+                panic!("poisoning the rwlock");
+            });
+            assert!(panicked.join().is_err());
+        });
+        let mutex = rwlock.into_mutex();
+        assert!(mutex.is_poisoned());
+        assert_eq!(mutex.into_inner().unwrap_err().into_inner(), 5);
+    }
+
+    #[test]
+    fn test_try_with() {
+        let rwlock = RwLock::new(1);
+        {
+            let _r = rwlock.read();
+            assert_eq!(rwlock.try_with_read(|v| *v + 1), Some(2));
+            assert_eq!(rwlock.try_with_write(|_| unreachable!()), None::<()>);
+        }
+        {
+            let _w = rwlock.write();
+            assert_eq!(rwlock.try_with_read(|_| unreachable!()), None::<()>);
+        }
+        assert_eq!(rwlock.try_with_write(|v| std::mem::replace(v, 3)), Some(1));
+        assert_eq!(*rwlock.read(), 3);
+    }
+
+    #[test]
+    fn test_debug() {
+        let rwlock = RwLock::with_name(1, "config");
+        assert_eq!(rwlock.name(), Some("config"));
+        assert_eq!(
+            format!("{rwlock:?}"),
+            r#"RwLock { name: "config", data: 1, poisoned: false, .. }"#
+        );
+        let _w = rwlock.write();
+        assert_eq!(
+            format!("{rwlock:?}"),
+            r#"RwLock { name: "config", data: <locked>, poisoned: false, .. }"#
+        );
+    }
+
+    #[test]
+    fn test_or_else() {
+        let rwlock = RwLock::new(vec![1, 2]);
+        assert_eq!(*rwlock.read_or_else(|_| unreachable!()), [1, 2]);
+
+        thread::scope(|s| {
+            let panicked = s.spawn(|| {
+                let mut w = rwlock.write();
+                w.push(3);
+                panic!("poisoning the rwlock halfway through");
+            });
+            assert!(panicked.join().is_err());
+        });
+        assert!(rwlock.is_poisoned());
+
+        rwlock.write_or_else(|v| v.truncate(2)).push(4);
+        assert!(!rwlock.is_poisoned());
+        assert_eq!(*rwlock.read_or_else(|_| unreachable!()), [1, 2, 4]);
+        assert_eq!(*rwlock.try_read().unwrap(), [1, 2, 4]);
+    }
+
+    #[test]
+    fn test_yielding() {
+        let rwlock = RwLock::new(0).with_yielding(16);
+        thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    for _ in 0..100 {
+                        *rwlock.write() += 1;
+                        assert!(*rwlock.read() <= 400);
+                    }
+                });
+            }
+        });
+        assert_eq!(*rwlock.read(), 400);
+    }
+
+    #[test]
+    fn test_map() {
+        let rwlock = RwLock::new(vec![3, 1, 2]);
+        assert_eq!(rwlock.read_map(|v| v.iter().sum::<i32>()), 6);
+        let max = rwlock.write_map(|v| {
+            v.sort();
+            v.last().copied()
+        });
+        assert_eq!(max, Some(3));
+        assert_eq!(rwlock.read_map(Vec::clone), [1, 2, 3]);
+        assert!(rwlock.try_write().is_ok());
+    }
+
+    #[test]
+    fn test_clone_read_guard() {
+        let rwlock = RwLock::new(vec![1, 2]);
+        let guard = rwlock.read();
+        let clone = guard.clone();
+        assert!(std::ptr::eq(&*guard, &*clone));
+        assert_eq!(rwlock.state.load(Relaxed), 2 * READER);
+
+        thread::scope(|s| {
+            let writer = s.spawn(|| rwlock.write().push(3));
+            // Wait until the writer is pending, the clones keep it out.
+            while rwlock.state.load(Relaxed) != 2 * READER + 1 {
+                thread::yield_now();
+            }
+            drop(guard);
+            assert_eq!(*clone, [1, 2]);
+            assert_eq!(rwlock.state.load(Relaxed), READER + 1);
+            // The last reader wakes up the writer.
+            drop(clone);
+            writer.join().unwrap();
+        });
+        assert_eq!(rwlock.state.load(Relaxed), 0);
+        assert_eq!(*rwlock.read(), [1, 2, 3]);
+    }
+
+    #[test]
+    fn test_downgrade() {
+        let rwlock = RwLock::new(0);
+        let readers_done = AtomicU32::new(0);
+        thread::scope(|s| {
+            let mut write = rwlock.write();
+            for _ in 0..3 {
+                s.spawn(|| {
+                    let value = *rwlock.read();
+                    assert_eq!(value, 1);
+                    readers_done.fetch_add(1, Relaxed);
+                });
+            }
+            *write = 1;
+            let read = write.downgrade();
+            // Readers get in alongside, writers don't.
+            while readers_done.load(Relaxed) != 3 {
+                thread::yield_now();
+            }
+            assert!(matches!(rwlock.try_write(), Err(TryLockError::WouldBlock)));
+            let writer = s.spawn(|| *rwlock.write() += 1);
+            assert_eq!(*read, 1);
+            drop(read);
+            writer.join().unwrap();
+        });
+        assert_eq!(*rwlock.read(), 2);
+    }
+
+    #[test]
+    fn test_upgradable() {
+        let rwlock = RwLock::new(0);
+        let stop = AtomicBool::new(false);
+        let seen_one = AtomicU32::new(0);
+        thread::scope(|s| {
+            for _ in 0..3 {
+                s.spawn(|| {
+                    let mut prev = 0;
+                    while !stop.load(Relaxed) {
+                        let value = *rwlock.read();
+                        assert!(value >= prev);
+                        if value == 1 {
+                            seen_one.fetch_add(1, Relaxed);
+                        }
+                        prev = value;
+                    }
+                });
+            }
+
+            let mut write = rwlock.write();
+            *write = 1;
+            let upgradable = write.downgrade_to_upgradable();
+            // Readers get in alongside, writers and other upgradable readers don't.
+            while seen_one.load(Relaxed) == 0 {
+                thread::yield_now();
+            }
+            assert!(matches!(rwlock.try_write(), Err(TryLockError::WouldBlock)));
+            let second = s.spawn(|| *rwlock.upgradable_read());
+            thread::sleep(Duration::from_millis(50));
+            assert!(!second.is_finished());
+
+            let mut write = upgradable.upgrade();
+            assert_eq!(*write, 1);
+            *write = 2;
+            let read = write.downgrade_to_upgradable().downgrade();
+            // Giving up the upgradable read lock lets the other one in.
+            assert_eq!(second.join().unwrap(), 2);
+            drop(read);
+
+            let upgradable = rwlock.upgradable_read();
+            assert_eq!(*upgradable, 2);
+            *upgradable.upgrade() = 3;
+            stop.store(true, Relaxed);
+        });
+        assert_eq!(rwlock.state.load(Relaxed), 0);
+        assert_eq!(*rwlock.read(), 3);
+    }
+
+    #[test]
+    fn test_upgrade_is_atomic() {
+        let rwlock = RwLock::new(0);
+        thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    for _ in 0..200 {
+                        let upgradable = rwlock.upgradable_read();
+                        let value = *upgradable;
+                        let mut write = upgradable.upgrade();
+                        // Nobody got to write in between.
+                        assert_eq!(*write, value);
+                        *write += 1;
+                    }
+                });
+            }
+            for _ in 0..2 {
+                s.spawn(|| {
+                    for _ in 0..200 {
+                        *rwlock.write() += 1;
+                        drop(rwlock.read());
+                    }
+                });
+            }
+        });
+        assert_eq!(rwlock.state.load(Relaxed), 0);
+        assert_eq!(*rwlock.read(), 1200);
+    }
+
+    #[test]
+    fn test_leak() {
+        static RWLOCK: RwLock<i32> = RwLock::new(0);
+        let value: &'static mut i32 = RWLOCK.write().leak();
+        *value += 1;
+        assert_eq!(*value, 1);
+        assert!(matches!(RWLOCK.try_read(), Err(TryLockError::WouldBlock)));
+        assert!(matches!(RWLOCK.try_write(), Err(TryLockError::WouldBlock)));
+    }
+
+    #[test]
+    fn test_data_ptr() {
+        let rwlock = RwLock::new(1);
+        {
+            let _w = rwlock.write();
+            unsafe { *rwlock.data_ptr() += 1 };
+        }
+        let r = rwlock.read();
+        assert_eq!(unsafe { *rwlock.data_ptr() }, 2);
+        assert_eq!(*r, 2);
+    }
+
+    #[cfg(feature = "rwlock-stats")]
+    #[test]
+    fn test_stats() {
+        let rwlock = RwLock::new(0);
+        assert_eq!(rwlock.stats(), Default::default());
+
+        thread::scope(|s| {
+            let reader = s.spawn(|| {
+                let guard = rwlock.read();
+                thread::sleep(std::time::Duration::from_millis(100));
+                *guard
+            });
+            // Let the reader in first, so the writers have to wait for it.
+            while rwlock.stats().reads == 0 {
+                thread::yield_now();
+            }
+            for _ in 0..2 {
+                s.spawn(|| {
+                    for _ in 0..10 {
+                        *rwlock.write() += 1;
+                        drop(rwlock.read());
+                    }
+                });
+            }
+            reader.join().unwrap();
+        });
+
+        let stats = rwlock.stats();
+        assert_eq!(stats.reads, 21);
+        assert_eq!(stats.writes, 20);
+        assert!(stats.writer_pending_sets >= 1);
+        assert!(stats.writer_waits >= 1);
+        assert_eq!(*rwlock.read(), 20);
+        assert_eq!(rwlock.stats().reads, 22);
+    }
+
+    #[test]
+    fn test_new_array() {
+        let shards: [RwLock<usize>; 3] = RwLock::new_array(|i| i * 10);
+        *shards[1].write() += 1;
+        assert_eq!(shards.each_ref().map(|shard| *shard.read()), [0, 11, 20]);
+    }
+
+    #[test]
+    fn test_replace_with() {
+        let rwlock = RwLock::new(String::from("a"));
+        rwlock.replace_with(|s| s + "b");
+        assert_eq!(*rwlock.read(), "ab");
+
+        thread::scope(|s| {
+            let panicked = s.spawn(|| rwlock.replace_with(|_| panic!("replacing failed")));
+            assert!(panicked.join().is_err());
+        });
+        assert!(rwlock.is_poisoned());
+        assert_eq!(*rwlock.read(), "");
+    }
+
+    #[test]
+    fn test_many_writers() {
+        let rwlock = RwLock::new(0);
+        thread::scope(|s| {
+            for _ in 0..8 {
+                s.spawn(|| {
+                    for i in 0..1000 {
+                        *rwlock.write() += 1;
+                        if i % 100 == 0 {
+                            drop(rwlock.read());
+                        }
+                    }
+                });
+            }
+        });
+        assert_eq!(*rwlock.read(), 8000);
+        assert_eq!(rwlock.state.load(Relaxed), 0);
+    }
+
+    #[test]
+    fn test_writer_not_starved() {
+        let rwlock = RwLock::new(0);
+        let stop = AtomicBool::new(false);
+        thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    // Overlapping readers, so the lock never becomes free on its own.
+                    while !stop.load(Relaxed) {
+                        let guard = rwlock.read();
+                        thread::yield_now();
+                        drop(guard);
+                    }
+                });
+            }
+            for i in 1..=20 {
+                // Wait until readers hold the lock again.
+                while rwlock.state.load(Relaxed) < 2 {
+                    thread::yield_now();
+                }
+                let start = Instant::now();
+                *rwlock.write() += 1;
+                assert!(start.elapsed() < Duration::from_secs(1));
+                assert_eq!(*rwlock.read(), i);
+            }
+            stop.store(true, Relaxed);
+        });
+    }
+
+    #[test]
+    fn test_const() {
+        static PLAIN: RwLock<i32> = RwLock::new(0);
+        static FAIR: RwLock<i32> = RwLock::new_eventually_fair(1, 4);
+        static NAMED: RwLock<i32> = RwLock::with_name(2, "named");
+        static YIELDING: RwLock<i32> = RwLock::new(3).with_yielding(10);
+
+        thread::scope(|s| {
+            s.spawn(|| *PLAIN.write() += 1);
+            s.spawn(|| *FAIR.write() += 1);
+        });
+        assert_eq!((*PLAIN.read(), *FAIR.read()), (1, 2));
+        assert_eq!(NAMED.name(), Some("named"));
+        assert_eq!(*YIELDING.read(), 3);
+    }
+
+    #[test]
+    fn test_stage() {
+        let rwlock = RwLock::new((0, 0));
+        let stop = AtomicBool::new(false);
+        thread::scope(|s| {
+            for _ in 0..2 {
+                s.spawn(|| {
+                    while !stop.load(Relaxed) {
+                        let pair = *rwlock.read();
+                        // Both halves of a commit, or neither.
+                        assert_eq!(pair.0, pair.1);
+                    }
+                });
+            }
+            for round in 1..=50 {
+                let mut staged = rwlock.stage();
+                staged.push(|pair| pair.0 += 1);
+                thread::yield_now();
+                assert_eq!(staged.read().0, round - 1);
+                staged.push(|pair| pair.1 += 1);
+                assert_eq!(staged.len(), 2);
+                staged.commit();
+            }
+            stop.store(true, Relaxed);
+        });
+        assert_eq!(*rwlock.read(), (50, 50));
+
+        let mut staged = rwlock.stage();
+        staged.push(|pair| pair.0 = 0);
+        drop(staged);
+        assert_eq!(*rwlock.read(), (50, 50));
+    }
 }
@@ -0,0 +1,129 @@
+use super::{condvar::Condvar, mutex::Mutex};
+
+/// Counting semaphore handing out permits in arrival order:
+/// a newly arriving `acquire` never takes a permit ahead of one that's already waiting.
+pub struct Semaphore {
+    state: Mutex<State>,
+    condvar: Condvar,
+}
+
+struct State {
+    permits: usize,
+    /// Ticket for the next `acquire` call.
+    next_ticket: u64,
+    /// Ticket of the waiter that gets the next permit.
+    serving: u64,
+}
+
+impl Semaphore {
+    pub const fn new(permits: usize) -> Self {
+        Self {
+            state: Mutex::new(State {
+                permits,
+                next_ticket: 0,
+                serving: 0,
+            }),
+            condvar: Condvar::new(),
+        }
+    }
+
+    pub fn acquire(&self) -> Permit<'_> {
+        let mut state = self.state.lock();
+        let ticket = state.next_ticket;
+        state.next_ticket += 1;
+        while state.serving != ticket || state.permits == 0 {
+            state = self.condvar.wait(state);
+        }
+        state.permits -= 1;
+        state.serving += 1;
+        drop(state);
+        // The next in line might be able to take a permit as well.
+        self.condvar.notify_all();
+        Permit { semaphore: self }
+    }
+
+    /// Only succeeds if a permit is available and nobody is waiting for one.
+    pub fn try_acquire(&self) -> Option<Permit<'_>> {
+        let mut state = self.state.lock();
+        if state.permits == 0 || state.serving != state.next_ticket {
+            return None;
+        }
+        state.permits -= 1;
+        state.next_ticket += 1;
+        state.serving += 1;
+        Some(Permit { semaphore: self })
+    }
+
+    pub fn available_permits(&self) -> usize {
+        self.state.lock().permits
+    }
+
+    fn release(&self) {
+        self.state.lock().permits += 1;
+        // Only the waiter holding the serving ticket proceeds, the others go back to sleep.
+        self.condvar.notify_all();
+    }
+}
+
+pub struct Permit<'a> {
+    semaphore: &'a Semaphore,
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        self.semaphore.release();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Semaphore;
+    use crate::primitives::mutex::Mutex;
+    use std::{
+        sync::atomic::{AtomicUsize, Ordering::Relaxed},
+        thread,
+    };
+
+    #[test]
+    fn test() {
+        let semaphore = Semaphore::new(3);
+        let holding = AtomicUsize::new(0);
+        thread::scope(|s| {
+            for _ in 0..8 {
+                s.spawn(|| {
+                    for _ in 0..50 {
+                        let _permit = semaphore.acquire();
+                        assert!(holding.fetch_add(1, Relaxed) < 3);
+                        holding.fetch_sub(1, Relaxed);
+                    }
+                });
+            }
+        });
+        assert_eq!(semaphore.available_permits(), 3);
+    }
+
+    #[test]
+    fn test_arrival_order() {
+        let semaphore = Semaphore::new(1);
+        let order = Mutex::new(Vec::new());
+        thread::scope(|s| {
+            let permit = semaphore.acquire();
+            assert!(semaphore.try_acquire().is_none());
+            for i in 0..8 {
+                let (semaphore, order) = (&semaphore, &order);
+                s.spawn(move || {
+                    let _permit = semaphore.acquire();
+                    order.lock().push(i);
+                });
+                // Wait until the thread is queued before the next one arrives.
+                while semaphore.state.lock().next_ticket != i + 2 {
+                    thread::yield_now();
+                }
+            }
+            drop(permit);
+        });
+        assert_eq!(*order.lock(), (0..8).collect::<Vec<_>>());
+        assert_eq!(semaphore.available_permits(), 1);
+        assert!(semaphore.try_acquire().is_some());
+    }
+}
@@ -0,0 +1,216 @@
+use std::{
+    cell::UnsafeCell,
+    ops::{Deref, DerefMut},
+    sync::atomic::{
+        AtomicU32, AtomicUsize,
+        Ordering::{Relaxed, Release, SeqCst},
+    },
+    thread,
+};
+
+use atomic_wait::{wait, wake_all, wake_one};
+
+/// Reader-writer lock that spreads the reader count over several shards, so readers on
+/// different threads mostly touch different cache lines instead of all contending on one.
+/// Writers pay for it, they have to wait for every shard to drain.
+///
+/// Writers are preferred: once one is waiting, new readers step back until it's done.
+pub struct ShardedRwLock<T> {
+    /// 0: unlocked
+    /// 1: write locked, nobody waiting
+    /// 2: write locked, readers or writers waiting
+    writer: AtomicU32,
+    /// Number of readers holding the lock, per shard.
+    shards: Box<[Shard]>,
+    value: UnsafeCell<T>,
+}
+
+/// Always padded to a cache line, unlike `CachePadded`, since that's the whole point here.
+#[repr(align(64))]
+struct Shard(AtomicU32);
+
+unsafe impl<T> Sync for ShardedRwLock<T> where T: Send + Sync {}
+
+impl<T> ShardedRwLock<T> {
+    /// One shard per available core.
+    pub fn new(data: T) -> Self {
+        Self::with_shards(data, thread::available_parallelism().map_or(1, |n| n.get()))
+    }
+
+    /// # Panics
+    ///
+    /// If `shards` is zero.
+    pub fn with_shards(data: T, shards: usize) -> Self {
+        assert!(shards > 0, "ShardedRwLock needs at least one shard");
+        Self {
+            writer: AtomicU32::new(0),
+            shards: (0..shards).map(|_| Shard(AtomicU32::new(0))).collect(),
+            value: UnsafeCell::new(data),
+        }
+    }
+
+    pub fn read(&self) -> ShardedReadGuard<'_, T> {
+        let shard = &self.shards[shard_index() % self.shards.len()].0;
+        loop {
+            // SeqCst on both sides: either we see the writer, or the writer sees our count.
+            shard.fetch_add(1, SeqCst);
+            if self.writer.load(SeqCst) == 0 {
+                return ShardedReadGuard {
+                    rwlock: self,
+                    shard,
+                };
+            }
+            // Step back so the writer gets in, and wait until it's done.
+            self.release_shard(shard);
+            let mut w = self.writer.load(Relaxed);
+            while w != 0 {
+                if w == 1 {
+                    if let Err(e) = self.writer.compare_exchange(1, 2, Relaxed, Relaxed) {
+                        w = e;
+                        continue;
+                    }
+                }
+                wait(&self.writer, 2);
+                w = self.writer.load(Relaxed);
+            }
+        }
+    }
+
+    pub fn write(&self) -> ShardedWriteGuard<'_, T> {
+        if self.writer.compare_exchange(0, 1, SeqCst, Relaxed).is_err() {
+            while self.writer.swap(2, SeqCst) != 0 {
+                wait(&self.writer, 2);
+            }
+        }
+        // New readers back off now, wait for the ones already in.
+        for shard in self.shards.iter() {
+            loop {
+                let readers = shard.0.load(SeqCst);
+                if readers == 0 {
+                    break;
+                }
+                wait(&shard.0, readers);
+            }
+        }
+        ShardedWriteGuard { rwlock: self }
+    }
+
+    pub fn shard_count(&self) -> usize {
+        self.shards.len()
+    }
+
+    fn release_shard(&self, shard: &AtomicU32) {
+        // Only the writer holding the lock waits on a shard.
+        if shard.fetch_sub(1, SeqCst) == 1 && self.writer.load(SeqCst) != 0 {
+            wake_one(shard);
+        }
+    }
+}
+
+/// Spreads threads over the shards by the order they first took a read lock in.
+fn shard_index() -> usize {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    thread_local! {
+        static INDEX: usize = NEXT.fetch_add(1, Relaxed);
+    }
+    INDEX.with(|index| *index)
+}
+
+pub struct ShardedReadGuard<'a, T> {
+    rwlock: &'a ShardedRwLock<T>,
+    shard: &'a AtomicU32,
+}
+
+impl<T> Deref for ShardedReadGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.rwlock.value.get() }
+    }
+}
+
+impl<T> Drop for ShardedReadGuard<'_, T> {
+    fn drop(&mut self) {
+        self.rwlock.release_shard(self.shard);
+    }
+}
+
+pub struct ShardedWriteGuard<'a, T> {
+    rwlock: &'a ShardedRwLock<T>,
+}
+
+impl<T> Deref for ShardedWriteGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.rwlock.value.get() }
+    }
+}
+
+impl<T> DerefMut for ShardedWriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.rwlock.value.get() }
+    }
+}
+
+impl<T> Drop for ShardedWriteGuard<'_, T> {
+    fn drop(&mut self) {
+        if self.rwlock.writer.swap(0, Release) == 2 {
+            wake_all(&self.rwlock.writer);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::ShardedRwLock;
+    use std::{
+        sync::atomic::{AtomicBool, Ordering::Relaxed},
+        thread,
+    };
+
+    #[test]
+    fn test() {
+        let rwlock = ShardedRwLock::with_shards(0, 4);
+        thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    for _ in 0..200 {
+                        *rwlock.write() += 1;
+                        assert!(*rwlock.read() <= 800);
+                    }
+                });
+            }
+        });
+        assert_eq!(*rwlock.read(), 800);
+        assert!(ShardedRwLock::new(()).shard_count() >= 1);
+    }
+
+    #[test]
+    fn test_writer_excludes_readers() {
+        let rwlock = ShardedRwLock::with_shards((), 3);
+        let writing = AtomicBool::new(false);
+        thread::scope(|s| {
+            for _ in 0..6 {
+                s.spawn(|| {
+                    for _ in 0..500 {
+                        let _guard = rwlock.read();
+                        assert!(!writing.load(Relaxed));
+                    }
+                });
+            }
+            for _ in 0..2 {
+                s.spawn(|| {
+                    for _ in 0..100 {
+                        let _guard = rwlock.write();
+                        assert!(!writing.swap(true, Relaxed));
+                        thread::yield_now();
+                        writing.store(false, Relaxed);
+                    }
+                });
+            }
+        });
+        assert!(rwlock.shards.iter().all(|shard| shard.0.load(Relaxed) == 0));
+        assert_eq!(rwlock.writer.load(Relaxed), 0);
+    }
+}
@@ -0,0 +1,82 @@
+use std::ops::Deref;
+
+use super::arc::Arc;
+
+/// Like `std::borrow::Cow`, but the shared value is owned by all clones together.
+/// Reading never clones, `to_mut` only clones while the value is shared.
+pub struct SharedCow<T> {
+    arc: Arc<T>,
+}
+
+impl<T: Clone> SharedCow<T> {
+    pub fn new(value: T) -> Self {
+        Self {
+            arc: Arc::new(value),
+        }
+    }
+
+    pub fn to_mut(&mut self) -> &mut T {
+        Arc::make_mut(&mut self.arc)
+    }
+
+    pub fn into_arc(self) -> Arc<T> {
+        self.arc
+    }
+}
+
+impl<T> From<Arc<T>> for SharedCow<T> {
+    fn from(arc: Arc<T>) -> Self {
+        Self { arc }
+    }
+}
+
+impl<T> Clone for SharedCow<T> {
+    fn clone(&self) -> Self {
+        Self {
+            arc: self.arc.clone(),
+        }
+    }
+}
+
+impl<T> Deref for SharedCow<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.arc
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::SharedCow;
+    use std::sync::atomic::{AtomicUsize, Ordering::Relaxed};
+
+    static CLONES: AtomicUsize = AtomicUsize::new(0);
+
+    struct Counted(Vec<i32>);
+
+    impl Clone for Counted {
+        fn clone(&self) -> Self {
+            CLONES.fetch_add(1, Relaxed);
+            Self(self.0.clone())
+        }
+    }
+
+    #[test]
+    fn test() {
+        let mut a = SharedCow::new(Counted(vec![1, 2]));
+        a.to_mut().0.push(3);
+        let mut b = a.clone();
+        assert_eq!(a.0, b.0);
+        assert_eq!(CLONES.load(Relaxed), 0);
+
+        b.to_mut().0.push(4);
+        assert_eq!(CLONES.load(Relaxed), 1);
+        assert_eq!((&a.0[..], &b.0[..]), (&[1, 2, 3][..], &[1, 2, 3, 4][..]));
+
+        // Both are unique now.
+        a.to_mut().0.clear();
+        b.to_mut().0.clear();
+        assert_eq!(CLONES.load(Relaxed), 1);
+    }
+}
@@ -0,0 +1,99 @@
+use std::thread;
+
+use super::{arc::Arc, event::Event, mutex::Mutex};
+
+/// Clonable join handle of a thread started by `spawn_shared`.
+pub struct SharedJoin<T> {
+    inner: Arc<Inner<T>>,
+}
+
+struct Inner<T> {
+    result: Mutex<Option<Arc<T>>>,
+    /// Set once the thread is done, whether it stored a result or panicked.
+    done: Event,
+}
+
+/// Like `thread::spawn`, but the result can be waited for from several places.
+pub fn spawn_shared<T, F>(f: F) -> SharedJoin<T>
+where
+    T: Send + Sync + 'static,
+    F: FnOnce() -> T + Send + 'static,
+{
+    let inner = Arc::new(Inner {
+        result: Mutex::new(None),
+        done: Event::new(),
+    });
+    let done = SetOnDrop(inner.clone());
+    thread::spawn(move || {
+        let result = Arc::new(f());
+        *done.0.result.lock() = Some(result);
+    });
+    SharedJoin { inner }
+}
+
+/// Wakes up the joiners even if `f` panics, they find no result then.
+struct SetOnDrop<T>(Arc<Inner<T>>);
+
+impl<T> Drop for SetOnDrop<T> {
+    fn drop(&mut self) {
+        self.0.done.set();
+    }
+}
+
+impl<T> SharedJoin<T> {
+    /// # Panics
+    ///
+    /// If the thread panicked.
+    pub fn join(&self) -> Arc<T> {
+        self.inner.done.wait();
+        self.inner
+            .result
+            .lock()
+            .clone()
+            .expect("the spawned thread panicked")
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.inner.done.is_set()
+    }
+}
+
+impl<T> Clone for SharedJoin<T> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{spawn_shared, Arc};
+    use std::{
+        panic::{catch_unwind, AssertUnwindSafe},
+        thread,
+        time::Duration,
+    };
+
+    #[test]
+    fn test() {
+        let handle = spawn_shared(|| {
+            thread::sleep(Duration::from_millis(50));
+            (1..=10).sum::<u32>()
+        });
+        let cloned = handle.clone();
+        let other = thread::spawn(move || cloned.join());
+        let result = handle.join();
+        assert_eq!(*result, 55);
+        assert!(Arc::ptr_eq(&result, &other.join().unwrap()));
+        assert!(handle.is_finished());
+    }
+
+    #[test]
+    fn test_panic() {
+        let handle = spawn_shared(|| -> u32 { panic!("computation failed") });
+        let cloned = handle.clone();
+        assert!(catch_unwind(AssertUnwindSafe(|| cloned.join())).is_err());
+        assert!(handle.is_finished());
+    }
+}
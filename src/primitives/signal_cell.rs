@@ -0,0 +1,80 @@
+use super::{condvar::Condvar, mutex::Mutex};
+
+/// Hands a value from one thread to another: `set` stores it and wakes a waiter,
+/// `take` blocks until there is one.
+pub struct SignalCell<T> {
+    value: Mutex<Option<T>>,
+    condvar: Condvar,
+}
+
+impl<T> SignalCell<T> {
+    pub const fn new() -> Self {
+        Self {
+            value: Mutex::new(None),
+            condvar: Condvar::new(),
+        }
+    }
+
+    /// Returns the value that wasn't taken yet, if there was one.
+    pub fn set(&self, value: T) -> Option<T> {
+        let old = self.value.lock().replace(value);
+        self.condvar.notify_one();
+        old
+    }
+
+    pub fn take(&self) -> T {
+        let mut value = self.value.lock();
+        loop {
+            if let Some(value) = value.take() {
+                return value;
+            }
+            value = self.condvar.wait(value);
+        }
+    }
+
+    pub fn try_take(&self) -> Option<T> {
+        self.value.lock().take()
+    }
+}
+
+impl<T> Default for SignalCell<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::SignalCell;
+    use std::thread;
+
+    #[test]
+    fn test() {
+        let requests = SignalCell::new();
+        let responses = SignalCell::new();
+        thread::scope(|s| {
+            s.spawn(|| {
+                while let Some(i) = requests.take() {
+                    assert!(responses.set(i * 2).is_none());
+                }
+            });
+
+            for i in 0..100 {
+                requests.set(Some(i));
+                assert_eq!(responses.take(), i * 2);
+            }
+            requests.set(None);
+        });
+        assert!(requests.try_take().is_none());
+        assert!(responses.try_take().is_none());
+    }
+
+    #[test]
+    fn test_set_twice() {
+        let cell = SignalCell::new();
+        assert_eq!(cell.set(1), None);
+        assert_eq!(cell.set(2), Some(1));
+        assert_eq!(cell.take(), 2);
+        assert_eq!(cell.try_take(), None);
+    }
+}
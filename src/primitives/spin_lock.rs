@@ -1,21 +1,52 @@
 use std::ops::{Deref, DerefMut};
 use std::{
     cell::UnsafeCell,
+    marker::PhantomData,
+    mem,
+    ptr::NonNull,
     sync::atomic::{
         AtomicBool,
-        Ordering::{Acquire, Release},
+        Ordering::{Acquire, Relaxed, Release},
     },
 };
 
+#[cfg(feature = "spin-lock-poison")]
+use super::poison::{self, LockResult, PoisonError};
+
+/// Like `std::sync::Mutex`, sharing the lock only requires `T: Send`:
+/// ```
+/// # use rust_concurrency::primitives::spin_lock::SpinLock;
+/// fn assert_sync<T: Sync>() {}
+/// assert_sync::<SpinLock<std::cell::Cell<i32>>>();
+/// ```
+/// ```compile_fail
+/// # use rust_concurrency::primitives::spin_lock::SpinLock;
+/// fn assert_sync<T: Sync>() {}
+/// assert_sync::<SpinLock<std::rc::Rc<i32>>>();
+/// ```
+///
+/// With the `spin-lock-poison` feature, a panic while holding the lock poisons it,
+/// see `lock_checked`. Without it, the lock doesn't track anything extra.
 pub struct SpinLock<T> {
     locked: AtomicBool,
+    #[cfg(feature = "spin-lock-poison")]
+    poison: poison::Flag,
     value: UnsafeCell<T>,
 }
 
 unsafe impl<T> Sync for SpinLock<T> where T: Send {}
 
+/// A shared guard hands out `&T`, so it must only be `Sync` if `T` is.
+/// Without the explicit impl it would be `Sync` whenever `SpinLock<T>` is, i.e. for any `T: Send`.
+/// ```compile_fail
+/// # use rust_concurrency::primitives::spin_lock::Guard;
+/// fn assert_sync<T: Sync>() {}
+/// assert_sync::<Guard<'static, std::cell::Cell<i32>>>();
+/// ```
 pub struct Guard<'a, T> {
     lock: &'a SpinLock<T>,
+    #[cfg(feature = "spin-lock-poison")]
+    poison: poison::Guard,
 }
 
 unsafe impl<T> Sync for Guard<'_, T> where T: Sync {}
@@ -24,15 +55,87 @@ impl<T> SpinLock<T> {
     pub const fn new(value: T) -> Self {
         Self {
             locked: AtomicBool::new(false),
+            #[cfg(feature = "spin-lock-poison")]
+            poison: poison::Flag::new(),
             value: UnsafeCell::new(value),
         }
     }
 
-    pub fn lock(&self) -> Guard<T> {
+    /// `N` independent locks, e.g. for striping a sharded structure.
+    /// `init` gets each lock's index.
+    pub fn new_array<const N: usize>(mut init: impl FnMut(usize) -> T) -> [Self; N] {
+        std::array::from_fn(|i| Self::new(init(i)))
+    }
+
+    /// Test and test-and-set: while the lock is taken, waiters spin on a plain load,
+    /// which keeps the cache line shared instead of bouncing it between them with writes.
+    pub fn lock(&self) -> Guard<'_, T> {
         while self.locked.swap(true, Acquire) {
-            std::hint::spin_loop();
+            while self.locked.load(Relaxed) {
+                std::hint::spin_loop();
+            }
         }
-        Guard { lock: self }
+        Guard {
+            lock: self,
+            #[cfg(feature = "spin-lock-poison")]
+            poison: self.poison.guard(),
+        }
+    }
+
+    /// Like `lock`, but reports if a thread panicked while holding the lock before.
+    #[cfg(feature = "spin-lock-poison")]
+    pub fn lock_checked(&self) -> LockResult<Guard<'_, T>> {
+        let guard = self.lock();
+        if self.poison.get() {
+            return Err(PoisonError::new(guard));
+        }
+        Ok(guard)
+    }
+
+    #[cfg(feature = "spin-lock-poison")]
+    pub fn is_poisoned(&self) -> bool {
+        self.poison.get()
+    }
+
+    /// Runs `f` with the lock held.
+    pub fn with<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
+        f(&mut self.lock())
+    }
+}
+
+impl<'a, T> Guard<'a, T> {
+    /// Narrows the guard down to a part of the protected value.
+    /// The lock stays held until the returned guard is dropped.
+    pub fn map<U>(mut self, f: impl FnOnce(&mut T) -> &mut U) -> MappedGuard<'a, U> {
+        let value = NonNull::from(f(&mut *self));
+        self.into_mapped(value)
+    }
+
+    /// Like `map`, but gives the original guard back if `f` returns `None`.
+    pub fn try_map<U>(
+        mut self,
+        f: impl FnOnce(&mut T) -> Option<&mut U>,
+    ) -> Result<MappedGuard<'a, U>, Self> {
+        match f(&mut *self) {
+            Some(value) => {
+                let value = NonNull::from(value);
+                Ok(self.into_mapped(value))
+            }
+            None => Err(self),
+        }
+    }
+
+    fn into_mapped<U>(self, value: NonNull<U>) -> MappedGuard<'a, U> {
+        let mapped = MappedGuard {
+            locked: &self.lock.locked,
+            #[cfg(feature = "spin-lock-poison")]
+            poison: (&self.lock.poison, self.poison),
+            value,
+            _marker: PhantomData,
+        };
+        // The mapped guard takes over unlocking.
+        mem::forget(self);
+        mapped
     }
 }
 
@@ -52,14 +155,49 @@ impl<T> DerefMut for Guard<'_, T> {
 
 impl<T> Drop for Guard<'_, T> {
     fn drop(&mut self) {
+        #[cfg(feature = "spin-lock-poison")]
+        self.lock.poison.done(&self.poison);
         self.lock.locked.store(false, Release);
     }
 }
 
+pub struct MappedGuard<'a, U> {
+    locked: &'a AtomicBool,
+    #[cfg(feature = "spin-lock-poison")]
+    poison: (&'a poison::Flag, poison::Guard),
+    value: NonNull<U>,
+    _marker: PhantomData<&'a mut U>,
+}
+
+unsafe impl<U> Send for MappedGuard<'_, U> where U: Send {}
+unsafe impl<U> Sync for MappedGuard<'_, U> where U: Sync {}
+
+impl<U> Deref for MappedGuard<'_, U> {
+    type Target = U;
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { self.value.as_ref() }
+    }
+}
+
+impl<U> DerefMut for MappedGuard<'_, U> {
+    fn deref_mut(&mut self) -> &mut U {
+        unsafe { self.value.as_mut() }
+    }
+}
+
+impl<U> Drop for MappedGuard<'_, U> {
+    fn drop(&mut self) {
+        #[cfg(feature = "spin-lock-poison")]
+        self.poison.0.done(&self.poison.1);
+        self.locked.store(false, Release);
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::SpinLock;
-    use std::thread;
+    use std::{sync::atomic::Ordering::Relaxed, thread};
 
     #[test]
     fn test() {
@@ -75,4 +213,92 @@ mod test {
         let g = lock.lock();
         assert!(*g == vec![1, 2, 3] || *g == vec![2, 3, 1]);
     }
+
+    #[test]
+    fn test_with() {
+        let lock = SpinLock::new(0);
+        thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    for _ in 0..100 {
+                        lock.with(|v| *v += 1);
+                    }
+                });
+            }
+        });
+        assert_eq!(lock.with(|v| *v), 400);
+        assert!(!lock.locked.load(Relaxed));
+    }
+
+    #[test]
+    fn test_map() {
+        let lock = SpinLock::new((1u8, 2u8));
+        {
+            let mut second = lock.lock().map(|pair| &mut pair.1);
+            *second += 40;
+            assert!(lock.locked.load(Relaxed));
+        }
+        assert!(!lock.locked.load(Relaxed));
+        assert_eq!(*lock.lock(), (1, 42));
+
+        match lock.lock().try_map(|_| None::<&mut u8>) {
+            Ok(_) => panic!("try_map should have failed"),
+            Err(guard) => assert_eq!(guard.0, 1),
+        }
+
+        let mut first = lock.lock().try_map(|pair| Some(&mut pair.0)).ok().unwrap();
+        *first = 7;
+        drop(first);
+        assert_eq!(*lock.lock(), (7, 42));
+    }
+
+    #[cfg(feature = "spin-lock-poison")]
+    #[test]
+    fn test_poison() {
+        let lock = SpinLock::new(0);
+        assert!(lock.lock_checked().is_ok());
+        thread::scope(|s| {
+            let panicked = s.spawn(|| {
+                let mut g = lock.lock();
+                *g += 1;
+                panic!("poisoning the spin lock");
+            });
+            assert!(panicked.join().is_err());
+        });
+        assert!(lock.is_poisoned());
+        let result = lock.lock_checked();
+        match result {
+            Err(err) => assert_eq!(*err.into_inner(), 1),
+            Ok(_) => panic!("expected a poisoned spin lock"),
+        }
+
+        // Mapped guards poison the lock too.
+        let lock = SpinLock::new((0, 0));
+        thread::scope(|s| {
+            let panicked = s.spawn(|| {
+                let _g = lock.lock().map(|v| &mut v.1);
+                panic!("poisoning through a mapped guard");
+            });
+            assert!(panicked.join().is_err());
+        });
+        assert!(lock.lock_checked().is_err());
+    }
+
+    #[test]
+    fn test_new_array() {
+        let shards: [SpinLock<usize>; 3] = SpinLock::new_array(|i| i * 10);
+        shards[1].with(|v| *v += 1);
+        assert_eq!(shards.each_ref().map(|shard| *shard.lock()), [0, 11, 20]);
+    }
+
+    #[test]
+    fn test_const() {
+        static LOCK: SpinLock<i32> = SpinLock::new(0);
+        thread::scope(|s| {
+            for _ in 0..2 {
+                s.spawn(|| LOCK.with(|v| *v += 1));
+            }
+        });
+        assert_eq!(*LOCK.lock(), 2);
+    }
 }
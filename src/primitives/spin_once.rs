@@ -0,0 +1,109 @@
+use std::sync::atomic::{
+    AtomicU8,
+    Ordering::{Acquire, Relaxed, Release},
+};
+
+const INCOMPLETE: u8 = 0;
+/// A thread is running the initializer, the others spin.
+const RUNNING: u8 = 1;
+const COMPLETE: u8 = 2;
+
+/// Like `std::sync::Once`, but waiting threads busy-wait instead of sleeping on a futex,
+/// so it's only a good fit for short initializers.
+pub struct SpinOnce {
+    state: AtomicU8,
+}
+
+impl SpinOnce {
+    pub const fn new() -> Self {
+        Self {
+            state: AtomicU8::new(INCOMPLETE),
+        }
+    }
+
+    /// Runs `f` on the first call only, other threads calling this meanwhile spin until it's done.
+    /// If `f` panics, the next caller runs its own `f` instead.
+    pub fn call_once(&self, f: impl FnOnce()) {
+        loop {
+            match self
+                .state
+                .compare_exchange_weak(INCOMPLETE, RUNNING, Acquire, Acquire)
+            {
+                Ok(_) => break,
+                Err(COMPLETE) => return,
+                Err(_) => {
+                    // Relaxed loads until it looks done, like `SpinLock::lock`.
+                    while self.state.load(Relaxed) == RUNNING {
+                        std::hint::spin_loop();
+                    }
+                }
+            }
+        }
+
+        let reset = ResetOnPanic(&self.state);
+        f();
+        std::mem::forget(reset);
+        self.state.store(COMPLETE, Release);
+    }
+
+    pub fn is_completed(&self) -> bool {
+        self.state.load(Acquire) == COMPLETE
+    }
+}
+
+impl Default for SpinOnce {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Lets the next caller try again.
+struct ResetOnPanic<'a>(&'a AtomicU8);
+
+impl Drop for ResetOnPanic<'_> {
+    fn drop(&mut self) {
+        self.0.store(INCOMPLETE, Release);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::SpinOnce;
+    use std::{
+        panic,
+        sync::atomic::{AtomicUsize, Ordering::Relaxed},
+        thread,
+    };
+
+    #[test]
+    fn test() {
+        let once = SpinOnce::new();
+        let calls = AtomicUsize::new(0);
+        thread::scope(|s| {
+            for _ in 0..8 {
+                s.spawn(|| {
+                    once.call_once(|| {
+                        calls.fetch_add(1, Relaxed);
+                    });
+                    // Whoever returns sees the initializer's effects.
+                    assert!(once.is_completed());
+                    assert_eq!(calls.load(Relaxed), 1);
+                });
+            }
+        });
+        assert_eq!(calls.load(Relaxed), 1);
+    }
+
+    #[test]
+    fn test_panic() {
+        let once = SpinOnce::new();
+        assert!(panic::catch_unwind(|| once.call_once(|| panic!("init failed"))).is_err());
+        assert!(!once.is_completed());
+
+        let mut ran = false;
+        once.call_once(|| ran = true);
+        assert!(ran);
+        assert!(once.is_completed());
+        once.call_once(|| unreachable!());
+    }
+}
@@ -0,0 +1,33 @@
+#![cfg(feature = "debug-counts")]
+
+use rust_concurrency::primitives::arc::Arc;
+use std::thread;
+
+#[test]
+fn test_debug_counts() {
+    let arc = Arc::new(vec![1, 2, 3]);
+    assert_eq!(Arc::debug_counts(&arc), (1, 1));
+
+    let weak = arc.downgrade();
+    let weak2 = weak.clone();
+    assert_eq!(Arc::debug_counts(&arc), (1, 3));
+
+    thread::scope(|s| {
+        for _ in 0..4 {
+            let (arc, weak2) = (arc.clone(), &weak2);
+            s.spawn(move || {
+                let upgraded = weak2.upgrade().unwrap();
+                assert!(Arc::ptr_eq(&arc, &upgraded));
+            });
+        }
+    });
+    assert_eq!(Arc::debug_counts(&arc), (1, 3));
+
+    drop(weak2);
+    let cloned = arc.clone();
+    assert_eq!(weak.debug_counts(), (2, 2));
+    drop((arc, cloned));
+    // The implicit weak pointer goes away with the last `Arc`.
+    assert_eq!(weak.debug_counts(), (0, 1));
+    assert!(weak.upgrade().is_none());
+}
@@ -0,0 +1,48 @@
+#![cfg(feature = "leak-detection")]
+
+use rust_concurrency::primitives::arc::{Arc, Weak};
+use std::{cell::RefCell, thread};
+
+struct Node {
+    parent: Option<Weak<RefCell<Node>>>,
+    children: Vec<Arc<RefCell<Node>>>,
+}
+
+// Only test in this binary, so no other test allocates `Arc`s concurrently.
+#[test]
+fn test_outstanding_allocations() {
+    assert_eq!(Arc::outstanding_allocations(), 0);
+
+    let shared = Arc::new(vec![1, 2, 3]);
+    let weak = shared.downgrade();
+    thread::scope(|s| {
+        for _ in 0..4 {
+            let shared = shared.clone();
+            s.spawn(move || assert_eq!(shared.len(), 3));
+        }
+    });
+    assert_eq!(Arc::outstanding_allocations(), 1);
+    drop(shared);
+    // The weak pointer still keeps the allocation around.
+    assert_eq!(Arc::outstanding_allocations(), 1);
+    drop(weak);
+    assert_eq!(Arc::outstanding_allocations(), 0);
+
+    {
+        let root = Arc::new(RefCell::new(Node {
+            parent: None,
+            children: Vec::new(),
+        }));
+        for _ in 0..3 {
+            let child = Arc::new(RefCell::new(Node {
+                parent: Some(root.downgrade()),
+                children: Vec::new(),
+            }));
+            root.borrow_mut().children.push(child);
+        }
+        let first = root.borrow().children[0].clone();
+        assert!(first.borrow().parent.as_ref().unwrap().upgrade().is_some());
+        assert_eq!(Arc::outstanding_allocations(), 4);
+    }
+    assert_eq!(Arc::outstanding_allocations(), 0);
+}